@@ -0,0 +1,323 @@
+// Shell completion scripts, generated from the same option tables the tools parse with
+
+use crate::{Opt, Takes};
+
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+}
+
+// What an option value or positional argument completes to
+pub enum Complete {
+    // A free-form value such as a number
+    Nothing,
+    Files,
+    Users,
+    Words(Vec<String>),
+    // A shell command printing the candidates, one per line
+    Command(&'static str),
+}
+
+pub struct Command<'a> {
+    pub name: &'a str,
+    pub options: &'a [Opt],
+    // What an option's value completes to; value options not listed complete nothing
+    pub values: Vec<(&'static str, Complete)>,
+    // What the positional arguments complete to, in order; the last one repeats
+    pub positionals: Vec<Complete>,
+}
+
+impl Command<'_> {
+    fn value_of(&self, opt: &Opt) -> &Complete {
+        self.values
+            .iter()
+            .find(|(long, _)| *long == opt.long)
+            .map(|(_, complete)| complete)
+            .unwrap_or(&Complete::Nothing)
+    }
+
+    // Every way to spell the option: --long, its aliases and -s
+    fn spellings(opt: &Opt) -> Vec<String> {
+        let mut names: Vec<String> = std::iter::once(opt.long)
+            .chain(opt.aliases.iter().copied())
+            .map(|name| format!("--{}", name))
+            .collect();
+        if let Some(short) = opt.short {
+            names.push(format!("-{}", short));
+        }
+        names
+    }
+}
+
+pub fn generate(shell: Shell, command: &Command) -> String {
+    match shell {
+        Shell::Bash => bash(command),
+        Shell::Zsh => zsh(command),
+        Shell::Fish => fish(command),
+    }
+}
+
+// Safe inside single quotes in all three shells
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn bash(command: &Command) -> String {
+    let function = format!("_{}", command.name.replace('-', "_"));
+    let reply = |complete: &Complete| match complete {
+        Complete::Nothing => "COMPREPLY=()".to_string(),
+        Complete::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+        Complete::Users => "COMPREPLY=($(compgen -u -- \"$cur\"))".to_string(),
+        Complete::Words(words) => format!(
+            "COMPREPLY=($(compgen -W {} -- \"$cur\"))",
+            single_quoted(&words.join(" "))
+        ),
+        Complete::Command(cmd) => format!("COMPREPLY=($(compgen -W \"$({})\" -- \"$cur\"))", cmd),
+    };
+
+    let mut script = format!(
+        "{}() {{\n    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\n    case \"$prev\" in\n",
+        function
+    );
+    for opt in command
+        .options
+        .iter()
+        .filter(|opt| opt.takes != Takes::Nothing)
+    {
+        script.push_str(&format!(
+            "        {})\n            {}\n            return\n            ;;\n",
+            Command::spellings(opt).join("|"),
+            reply(command.value_of(opt))
+        ));
+    }
+    script.push_str("    esac\n\n");
+
+    let all: Vec<String> = command
+        .options
+        .iter()
+        .flat_map(Command::spellings)
+        .collect();
+    script.push_str(&format!(
+        "    if [[ \"$cur\" == -* ]]; then\n        COMPREPLY=($(compgen -W {} -- \"$cur\"))\n        return\n    fi\n\n",
+        single_quoted(&all.join(" "))
+    ));
+
+    // Count the positional arguments before the word being completed, stepping over the
+    // values of options that always take one
+    let takes_value: Vec<String> = command
+        .options
+        .iter()
+        .filter(|opt| opt.takes == Takes::Value)
+        .flat_map(Command::spellings)
+        .collect();
+    script.push_str("    local i n=0\n    for ((i = 1; i < COMP_CWORD; i++)); do\n        case \"${COMP_WORDS[i]}\" in\n");
+    if !takes_value.is_empty() {
+        script.push_str(&format!(
+            "            {}) ((i++)) ;;\n",
+            takes_value.join("|")
+        ));
+    }
+    script.push_str("            -?*) ;;\n            *) ((n++)) ;;\n        esac\n    done\n\n");
+
+    script.push_str("    case $n in\n");
+    if let Some((last, first)) = command.positionals.split_last() {
+        for (n, complete) in first.iter().enumerate() {
+            script.push_str(&format!("        {}) {} ;;\n", n, reply(complete)));
+        }
+        script.push_str(&format!("        *) {} ;;\n", reply(last)));
+    }
+    script.push_str("    esac\n}\n\n");
+    script.push_str(&format!("complete -F {} {}\n", function, command.name));
+    script
+}
+
+fn zsh(command: &Command) -> String {
+    let action = |complete: &Complete| match complete {
+        Complete::Nothing => " ".to_string(),
+        Complete::Files => "_files".to_string(),
+        Complete::Users => "_users".to_string(),
+        Complete::Words(words) => format!("({})", words.join(" ")),
+        Complete::Command(cmd) => format!("{{compadd -- ${{(f)\"$({})\"}}}}", cmd),
+    };
+
+    let mut specs = Vec::new();
+    for opt in command.options {
+        for spelling in Command::spellings(opt) {
+            let spec = match opt.takes {
+                Takes::Nothing => spelling,
+                Takes::Value => format!("{}:value:{}", spelling, action(command.value_of(opt))),
+                Takes::OptionalValue => {
+                    format!("{}::value:{}", spelling, action(command.value_of(opt)))
+                }
+            };
+            specs.push(single_quoted(&spec));
+        }
+    }
+    if let Some((last, first)) = command.positionals.split_last() {
+        for (n, complete) in first.iter().enumerate() {
+            specs.push(single_quoted(&format!(
+                "{}:argument:{}",
+                n + 1,
+                action(complete)
+            )));
+        }
+        specs.push(single_quoted(&format!("*:argument:{}", action(last))));
+    }
+
+    format!(
+        "#compdef {name}\n\n_{function}() {{\n    _arguments -s \\\n        {specs}\n}}\n\n_{function} \"$@\"\n",
+        name = command.name,
+        function = command.name.replace('-', "_"),
+        specs = specs.join(" \\\n        ")
+    )
+}
+
+fn fish(command: &Command) -> String {
+    let action = |complete: &Complete| match complete {
+        Complete::Nothing => String::new(),
+        Complete::Files => " -F".to_string(),
+        Complete::Users => " -a '(__fish_complete_users)'".to_string(),
+        Complete::Words(words) => format!(" -a {}", single_quoted(&words.join(" "))),
+        Complete::Command(cmd) => format!(" -a {}", single_quoted(&format!("({})", cmd))),
+    };
+
+    // Files are only offered where a positional or option value asks for them
+    let mut script = format!("complete -c {} -f\n", command.name);
+    for opt in command.options {
+        let mut line = format!("complete -c {} -l {}", command.name, opt.long);
+        if let Some(short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        for alias in opt.aliases {
+            line.push_str(&format!(" -l {}", alias));
+        }
+        match opt.takes {
+            Takes::Nothing => {}
+            Takes::Value => {
+                line.push_str(" -r");
+                line.push_str(&action(command.value_of(opt)));
+            }
+            Takes::OptionalValue => line.push_str(&action(command.value_of(opt))),
+        }
+        script.push_str(&line);
+        script.push('\n');
+    }
+
+    if let Some((last, first)) = command.positionals.split_last() {
+        let mut earlier = Vec::new();
+        for (n, complete) in first.iter().enumerate() {
+            let condition = format!("__fish_is_nth_token {}", n + 1);
+            script.push_str(&format!(
+                "complete -c {} -n {}{}\n",
+                command.name,
+                single_quoted(&condition),
+                action(complete)
+            ));
+            earlier.push(format!("not {}", condition));
+        }
+        let condition = if earlier.is_empty() {
+            String::new()
+        } else {
+            format!(" -n {}", single_quoted(&earlier.join("; and ")))
+        };
+        script.push_str(&format!(
+            "complete -c {}{}{}\n",
+            command.name,
+            condition,
+            action(last)
+        ));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: &[Opt] = &[
+        Opt::flag("all", Some('a')),
+        Opt::value("timeout", Some('t')),
+        Opt::optional_value("user", None),
+        Opt::flag("yes", Some('y')).aliases(&["no-confirm"]),
+    ];
+
+    fn script(shell: Shell) -> String {
+        let command = Command {
+            name: "sig-test",
+            options: OPTIONS,
+            values: vec![("user", Complete::Users)],
+            positionals: vec![
+                Complete::Words(vec!["it's".into(), "term".into()]),
+                Complete::Command("ps -eo comm="),
+            ],
+        };
+        generate(shell, &command)
+    }
+
+    #[test]
+    fn single_quotes_are_closed_and_escaped() {
+        assert_eq!(single_quoted("it's"), "'it'\\''s'");
+        assert!(script(Shell::Bash).contains("compgen -W 'it'\\''s term' -- \"$cur\""));
+        assert!(script(Shell::Fish).contains(" -a 'it'\\''s term'"));
+    }
+
+    #[test]
+    fn bash_steps_over_option_values() {
+        let script = script(Shell::Bash);
+        // Only --timeout always takes the next word, so only it is stepped over when counting
+        // positionals
+        assert!(script.contains("            --timeout|-t) ((i++)) ;;\n            -?*) ;;\n"));
+        assert!(!script.contains("--user) ((i++))"));
+        assert!(
+            script.contains("        --user)\n            COMPREPLY=($(compgen -u -- \"$cur\"))\n")
+        );
+        assert!(script
+            .contains("        0) COMPREPLY=($(compgen -W 'it'\\''s term' -- \"$cur\")) ;;\n"));
+        assert!(script
+            .contains("        *) COMPREPLY=($(compgen -W \"$(ps -eo comm=)\" -- \"$cur\")) ;;\n"));
+        assert!(script.ends_with("complete -F _sig_test sig-test\n"));
+    }
+
+    #[test]
+    fn zsh_specs() {
+        let script = script(Shell::Zsh);
+        assert!(script.starts_with("#compdef sig-test\n"));
+        for spec in [
+            "'--all'",
+            "'-a'",
+            "'--timeout:value: '",
+            "'--user::value:_users'",
+            "'--no-confirm'",
+            "'1:argument:(it'\\''s term)'",
+            "'*:argument:{compadd -- ${(f)\"$(ps -eo comm=)\"}}'",
+        ] {
+            assert!(script.contains(spec), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn fish_positional_conditions() {
+        let script = script(Shell::Fish);
+        assert!(script.starts_with("complete -c sig-test -f\n"));
+        assert!(script.contains("complete -c sig-test -l timeout -s t -r\n"));
+        assert!(script.contains("complete -c sig-test -l yes -s y -l no-confirm\n"));
+        assert!(
+            script.contains("complete -c sig-test -n '__fish_is_nth_token 1' -a 'it'\\''s term'\n")
+        );
+        assert!(script.ends_with(
+            "complete -c sig-test -n 'not __fish_is_nth_token 1' -a '(ps -eo comm=)'\n"
+        ));
+    }
+}
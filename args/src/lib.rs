@@ -0,0 +1,298 @@
+// Command-line parsing shared by mkdev and sig. Options may come before, between or after
+// the positional arguments, and an option the tool doesn't know is an error rather than
+// being silently ignored.
+//
+// Accepted forms: `--name`, `--name value`, `--name=value`, `-n`, `-n value`, `-nvalue` and
+// clusters of short flags such as `-an`. A lone `-` is a positional argument (stdin, by
+// convention) and everything after `--` is positional too.
+
+pub mod completions;
+
+// What an option expects after it
+#[derive(Clone, Copy, PartialEq)]
+pub enum Takes {
+    Nothing,
+    // Always takes the next argument, even one starting with `-`
+    Value,
+    // Takes the next argument only if it doesn't look like an option and another
+    // positional argument follows it, so `--user alice python` and `--user python` both
+    // mean what they say. `--name=value` always works.
+    OptionalValue,
+}
+
+pub struct Opt {
+    // Without the leading dashes; also the name to look the option up by
+    pub long: &'static str,
+    pub aliases: &'static [&'static str],
+    pub short: Option<char>,
+    pub takes: Takes,
+}
+
+impl Opt {
+    pub const fn flag(long: &'static str, short: Option<char>) -> Opt {
+        Opt {
+            long,
+            aliases: &[],
+            short,
+            takes: Takes::Nothing,
+        }
+    }
+
+    pub const fn value(long: &'static str, short: Option<char>) -> Opt {
+        Opt {
+            long,
+            aliases: &[],
+            short,
+            takes: Takes::Value,
+        }
+    }
+
+    pub const fn optional_value(long: &'static str, short: Option<char>) -> Opt {
+        Opt {
+            long,
+            aliases: &[],
+            short,
+            takes: Takes::OptionalValue,
+        }
+    }
+
+    // Other long names the option goes by, e.g. `--no-confirm` for `--yes`
+    pub const fn aliases(mut self, aliases: &'static [&'static str]) -> Opt {
+        self.aliases = aliases;
+        self
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        self.long == name || self.aliases.contains(&name)
+    }
+}
+
+// The options that were given, by long name and in command-line order, and the positional
+// arguments
+pub struct Parsed {
+    options: Vec<(&'static str, Option<String>)>,
+    pub positionals: Vec<String>,
+}
+
+impl Parsed {
+    // Whether the option was given at all, with or without a value
+    pub fn flag(&self, long: &str) -> bool {
+        self.options.iter().any(|(name, _)| *name == long)
+    }
+
+    // The value of the last occurrence of the option, so a later one overrides an earlier
+    pub fn value(&self, long: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == long)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    // For an optional-value option: None if it wasn't given, Some(None) if it was given on
+    // its own
+    pub fn optional_value(&self, long: &str) -> Option<Option<&str>> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == long)
+            .map(|(_, value)| value.as_deref())
+    }
+}
+
+// Parse everything after the program name against `options`. The error is a message fit to
+// print after "Error: ".
+pub fn parse<I>(options: &[Opt], args: I) -> Result<Parsed, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let args: Vec<String> = args.into_iter().collect();
+    let mut parsed = Parsed {
+        options: Vec::new(),
+        positionals: Vec::new(),
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+
+        if arg == "--" {
+            parsed.positionals.extend(args[i..].iter().cloned());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let opt = options
+                .iter()
+                .find(|opt| opt.is_named(name))
+                .ok_or_else(|| format!("Unknown option '--{}'", name))?;
+            let value = match (opt.takes, inline) {
+                (Takes::Nothing, Some(_)) => {
+                    return Err(format!("Option '--{}' does not take a value", name));
+                }
+                (Takes::Nothing, None) => None,
+                (_, Some(value)) => Some(value),
+                (Takes::Value, None) => Some(next_value(&args, &mut i, &format!("--{}", name))?),
+                (Takes::OptionalValue, None) => next_optional_value(&args, &mut i),
+            };
+            parsed.options.push((opt.long, value));
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // A cluster of short flags; the first one that takes a value takes the rest of
+            // the cluster, or the next argument if there is no rest
+            let cluster = &arg[1..];
+            for (at, c) in cluster.char_indices() {
+                let opt = options
+                    .iter()
+                    .find(|opt| opt.short == Some(c))
+                    .ok_or_else(|| format!("Unknown option '-{}'", c))?;
+                if opt.takes == Takes::Nothing {
+                    parsed.options.push((opt.long, None));
+                    continue;
+                }
+                let rest = &cluster[at + c.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    Some(rest.to_string())
+                } else if opt.takes == Takes::Value {
+                    Some(next_value(&args, &mut i, &format!("-{}", c))?)
+                } else {
+                    next_optional_value(&args, &mut i)
+                };
+                parsed.options.push((opt.long, value));
+                break;
+            }
+        } else {
+            parsed.positionals.push(arg.clone());
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn next_value(args: &[String], i: &mut usize, option: &str) -> Result<String, String> {
+    let value = args
+        .get(*i)
+        .ok_or_else(|| format!("Option '{}' needs a value", option))?;
+    *i += 1;
+    Ok(value.clone())
+}
+
+fn next_optional_value(args: &[String], i: &mut usize) -> Option<String> {
+    let looks_positional = |arg: &String| !arg.starts_with('-') || arg == "-";
+    let next = args.get(*i).filter(|arg| looks_positional(arg))?;
+    if !args[*i + 1..].iter().any(looks_positional) {
+        return None;
+    }
+    *i += 1;
+    Some(next.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: &[Opt] = &[
+        Opt::flag("all", Some('a')),
+        Opt::flag("dry-run", Some('n')),
+        Opt::value("timeout", Some('t')),
+        Opt::optional_value("user", None),
+        Opt::flag("yes", Some('y')).aliases(&["no-confirm"]),
+    ];
+
+    fn parse_args(args: &[&str]) -> Result<Parsed, String> {
+        parse(OPTIONS, args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_anywhere() {
+        let parsed = parse_args(&["kill", "--all", "chrome"]).unwrap();
+        assert!(parsed.flag("all"));
+        assert!(!parsed.flag("dry-run"));
+        assert_eq!(parsed.positionals, ["kill", "chrome"]);
+
+        let parsed = parse_args(&["--all", "kill", "chrome", "-n"]).unwrap();
+        assert!(parsed.flag("all") && parsed.flag("dry-run"));
+        assert_eq!(parsed.positionals, ["kill", "chrome"]);
+    }
+
+    #[test]
+    fn values() {
+        let parsed = parse_args(&["--timeout", "5", "term", "-t", "-3"]).unwrap();
+        // The last one wins, and a value may start with a dash
+        assert_eq!(parsed.value("timeout"), Some("-3"));
+        assert_eq!(parsed.positionals, ["term"]);
+
+        let parsed = parse_args(&["--timeout=5", "-t7", "term"]).unwrap();
+        assert_eq!(parsed.value("timeout"), Some("7"));
+
+        let parsed = parse_args(&["--timeout=", "term"]).unwrap();
+        assert_eq!(parsed.value("timeout"), Some(""));
+    }
+
+    #[test]
+    fn short_clusters() {
+        let parsed = parse_args(&["-an", "x"]).unwrap();
+        assert!(parsed.flag("all") && parsed.flag("dry-run"));
+
+        let parsed = parse_args(&["-at", "9", "x"]).unwrap();
+        assert!(parsed.flag("all"));
+        assert_eq!(parsed.value("timeout"), Some("9"));
+        assert_eq!(parsed.positionals, ["x"]);
+
+        let parsed = parse_args(&["-at9n", "x"]).unwrap();
+        assert_eq!(parsed.value("timeout"), Some("9n"));
+        assert!(!parsed.flag("dry-run"));
+    }
+
+    #[test]
+    fn optional_values() {
+        let parsed = parse_args(&["--user", "alice", "python"]).unwrap();
+        assert_eq!(parsed.optional_value("user"), Some(Some("alice")));
+        assert_eq!(parsed.positionals, ["python"]);
+
+        let parsed = parse_args(&["--user", "python"]).unwrap();
+        assert_eq!(parsed.optional_value("user"), Some(None));
+        assert_eq!(parsed.positionals, ["python"]);
+
+        let parsed = parse_args(&["--user=bob"]).unwrap();
+        assert_eq!(parsed.optional_value("user"), Some(Some("bob")));
+
+        let parsed = parse_args(&["python"]).unwrap();
+        assert_eq!(parsed.optional_value("user"), None);
+    }
+
+    #[test]
+    fn aliases_are_stored_under_the_long_name() {
+        let parsed = parse_args(&["--no-confirm"]).unwrap();
+        assert!(parsed.flag("yes"));
+    }
+
+    #[test]
+    fn double_dash_and_lone_dash() {
+        let parsed = parse_args(&["-a", "--", "--all", "-n", "-"]).unwrap();
+        assert!(parsed.flag("all"));
+        assert!(!parsed.flag("dry-run"));
+        assert_eq!(parsed.positionals, ["--all", "-n", "-"]);
+
+        let parsed = parse_args(&["-", "/dev/sdb"]).unwrap();
+        assert_eq!(parsed.positionals, ["-", "/dev/sdb"]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |args: &[&str]| parse_args(args).err().unwrap();
+        assert_eq!(error(&["--bogus"]), "Unknown option '--bogus'");
+        assert_eq!(error(&["--bogus=1"]), "Unknown option '--bogus'");
+        assert_eq!(error(&["-ax"]), "Unknown option '-x'");
+        assert_eq!(error(&["--timeout"]), "Option '--timeout' needs a value");
+        assert_eq!(error(&["x", "-t"]), "Option '-t' needs a value");
+        assert_eq!(
+            error(&["--all=yes"]),
+            "Option '--all' does not take a value"
+        );
+    }
+}
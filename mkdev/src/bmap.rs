@@ -0,0 +1,228 @@
+// Block maps in the bmaptool XML format. A bmap lists which blocks of a sparse image hold
+// data, so only those need to be read and written.
+
+use crate::{skip_source, to_hex, ChecksumAlgo, SourceFile};
+use sha2::digest::DynDigest;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// A run of mapped bytes in the image, with the digest bmaptool recorded for it
+pub struct MappedRange {
+    pub start: u64,
+    pub end: u64,
+    pub checksum: Option<String>,
+}
+
+impl MappedRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+pub struct BlockMap {
+    pub image_size: u64,
+    pub block_size: u64,
+    // None when the bmap has no per-range digests, or uses one mkdev can't compute
+    pub checksum_algo: Option<ChecksumAlgo>,
+    pub ranges: Vec<MappedRange>,
+}
+
+impl BlockMap {
+    pub fn parse(xml: &str) -> Result<BlockMap, String> {
+        let number = |tag: &str| -> Result<u64, String> {
+            let value = element_text(xml, tag).ok_or_else(|| format!("missing <{}>", tag))?;
+            value
+                .parse()
+                .map_err(|_| format!("<{}> is not a number: '{}'", tag, value))
+        };
+        let image_size = number("ImageSize")?;
+        let block_size = number("BlockSize")?;
+        if block_size == 0 {
+            return Err("<BlockSize> is zero".to_string());
+        }
+
+        // Version 1.x files carry SHA1 digests in a `sha1` attribute, which mkdev doesn't check
+        let checksum_algo = match element_text(xml, "ChecksumType").map(str::to_lowercase) {
+            Some(kind) if kind == "sha256" => Some(ChecksumAlgo::Sha256),
+            Some(kind) if kind == "md5" => Some(ChecksumAlgo::Md5),
+            _ => None,
+        };
+
+        let block_map = element_text(xml, "BlockMap").ok_or("missing <BlockMap>")?;
+        let mut ranges: Vec<MappedRange> = Vec::new();
+        let mut rest = block_map;
+        while let Some(open) = rest.find("<Range") {
+            rest = &rest[open + "<Range".len()..];
+            let tag_end = rest.find('>').ok_or("unterminated <Range> tag")?;
+            let attributes = &rest[..tag_end];
+            rest = &rest[tag_end + 1..];
+            let close = rest.find("</Range>").ok_or("missing </Range>")?;
+            let blocks = rest[..close].trim();
+            rest = &rest[close + "</Range>".len()..];
+
+            let (first, last) = match blocks.split_once('-') {
+                Some((first, last)) => (first.trim(), last.trim()),
+                None => (blocks, blocks),
+            };
+            let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(first), Ok(last)) if first <= last => (first, last),
+                _ => return Err(format!("invalid block range '{}'", blocks)),
+            };
+
+            let start = first * block_size;
+            let end = ((last + 1) * block_size).min(image_size);
+            if start >= end {
+                return Err(format!("block range '{}' is outside the image", blocks));
+            }
+            if ranges.last().is_some_and(|previous| previous.end > start) {
+                return Err(format!("block range '{}' is out of order", blocks));
+            }
+            ranges.push(MappedRange {
+                start,
+                end,
+                checksum: attribute(attributes, "chksum").map(str::to_lowercase),
+            });
+        }
+
+        Ok(BlockMap {
+            image_size,
+            block_size,
+            checksum_algo,
+            ranges,
+        })
+    }
+
+    pub fn mapped_size(&self) -> u64 {
+        self.ranges.iter().map(MappedRange::len).sum()
+    }
+
+    // Where the `logical`-th mapped byte lives in the image
+    pub fn image_offset(&self, logical: u64) -> u64 {
+        let mut remaining = logical;
+        for range in &self.ranges {
+            if remaining < range.len() {
+                return range.start + remaining;
+            }
+            remaining -= range.len();
+        }
+        self.image_size
+    }
+}
+
+fn element_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim())
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("{}=\"", name);
+    let start = attributes.find(&key)? + key.len();
+    let end = start + attributes[start..].find('"')?;
+    Some(&attributes[start..end])
+}
+
+// Moving forward over a hole: seekable sources jump, decompressors read and discard
+pub trait SkipForward {
+    fn skip_forward(&mut self, bytes: u64) -> io::Result<()>;
+}
+
+impl SkipForward for &mut File {
+    fn skip_forward(&mut self, bytes: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(bytes as i64)).map(|_| ())
+    }
+}
+
+impl SkipForward for &mut SourceFile {
+    fn skip_forward(&mut self, bytes: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(bytes as i64)).map(|_| ())
+    }
+}
+
+impl SkipForward for Box<dyn Read + '_> {
+    fn skip_forward(&mut self, bytes: u64) -> io::Result<()> {
+        skip_source(self, bytes)
+    }
+}
+
+// Reads only the mapped ranges of an image, back to back, starting from the inner reader's
+// current position as image offset 0. When the map has digests, each range is checked as it
+// is read and a mismatch fails the read.
+pub struct MappedReader<'a, R> {
+    inner: R,
+    map: &'a BlockMap,
+    index: usize,
+    position: u64,
+    hasher: Option<Box<dyn DynDigest>>,
+}
+
+impl<'a, R: Read + SkipForward> MappedReader<'a, R> {
+    pub fn new(inner: R, map: &'a BlockMap, check_digests: bool) -> MappedReader<'a, R> {
+        let hasher = map
+            .checksum_algo
+            .filter(|_| check_digests)
+            .map(ChecksumAlgo::hasher);
+        MappedReader {
+            inner,
+            map,
+            index: 0,
+            position: 0,
+            hasher,
+        }
+    }
+
+    fn finish_range(&mut self, range: &MappedRange) -> io::Result<()> {
+        let (Some(hasher), Some(expected)) = (self.hasher.as_mut(), range.checksum.as_ref()) else {
+            return Ok(());
+        };
+        let computed = to_hex(&hasher.finalize_reset());
+        if &computed != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "bmap checksum mismatch for bytes {}-{} of the image",
+                    range.start, range.end
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + SkipForward> Read for MappedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let map = self.map;
+        while let Some(range) = map.ranges.get(self.index) {
+            if self.position == range.end {
+                self.finish_range(range)?;
+                self.index += 1;
+                continue;
+            }
+            if self.position < range.start {
+                self.inner.skip_forward(range.start - self.position)?;
+                self.position = range.start;
+            }
+
+            let want = buf.len().min((range.end - self.position) as usize);
+            let n = self.inner.read(&mut buf[..want])?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "image ended before the end of its block map",
+                ));
+            }
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf[..n]);
+            }
+            self.position += n as u64;
+            return Ok(n);
+        }
+        Ok(0)
+    }
+}
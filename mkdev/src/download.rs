@@ -0,0 +1,208 @@
+// http:// and https:// sources, streamed through curl so that TLS, proxies and redirects
+// work the way they do everywhere else on the system. curl is asked to include the response
+// headers in its output: one block per response, redirects first, then the body.
+
+use mkdev::{to_hex, ChecksumAlgo};
+use sha2::digest::DynDigest;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub fn is_url(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+pub struct Download {
+    curl: Child,
+    body: BufReader<ChildStdout>,
+    // From Content-Length, when the server sends one
+    pub size: Option<u64>,
+    hasher: Option<Box<dyn DynDigest>>,
+    received: u64,
+    started: Instant,
+    finished: bool,
+}
+
+impl Download {
+    // Start the request and read up to the body, failing on anything but a 2xx response
+    pub fn open(url: &str) -> io::Result<Download> {
+        let mut curl = Command::new("curl")
+            .args([
+                "--silent",
+                "--show-error",
+                "--location",
+                "--include",
+                "--suppress-connect-headers",
+                "--proto",
+                "=http,https",
+                "--proto-redir",
+                "=http,https",
+                "--",
+                url,
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "reading from a URL needs curl, which was not found",
+                ),
+                _ => e,
+            })?;
+        let stdout = curl.stdout.take().expect("curl's stdout is piped");
+        let mut download = Download {
+            curl,
+            body: BufReader::new(stdout),
+            size: None,
+            hasher: None,
+            received: 0,
+            started: Instant::now(),
+            finished: false,
+        };
+
+        loop {
+            let Some((status, headers)) = download.read_response_head()? else {
+                return Err(download.curl_error());
+            };
+            // 1xx responses come before the real one, and -L follows 3xx ones that carry a
+            // Location, each with its own header block
+            let code = status.split_whitespace().nth(1).unwrap_or("");
+            let redirected = code.starts_with('3') && header(&headers, "location").is_some();
+            if code.starts_with('1') || redirected {
+                continue;
+            }
+            if !code.starts_with('2') {
+                download.curl.kill().ok();
+                download.curl.wait().ok();
+                return Err(io::Error::other(format!(
+                    "server answered {}",
+                    status.split_once(' ').map_or(status.as_str(), |(_, s)| s)
+                )));
+            }
+            download.size = header(&headers, "content-length").and_then(|len| len.parse().ok());
+            break;
+        }
+        download.started = Instant::now();
+        Ok(download)
+    }
+
+    // Hash the body as it is read, for --sha256 and --md5
+    pub fn hash_with(&mut self, algo: ChecksumAlgo) {
+        self.hasher = Some(algo.hasher());
+    }
+
+    // The first bytes of the body, without consuming them
+    pub fn peek(&mut self) -> io::Result<&[u8]> {
+        self.body.fill_buf()
+    }
+
+    // The hex digest of the body, once it has been read to the end
+    pub fn digest(&mut self) -> Option<String> {
+        if !self.finished {
+            return None;
+        }
+        Some(to_hex(&self.hasher.take()?.finalize()))
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // The status line and headers of the next response, or None if curl gave up first
+    fn read_response_head(&mut self) -> io::Result<Option<(String, Vec<String>)>> {
+        let Some(status) = self.read_header_line()? else {
+            return Ok(None);
+        };
+        let mut headers = Vec::new();
+        loop {
+            match self.read_header_line()? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => break,
+                Some(line) => headers.push(line),
+            }
+        }
+        Ok(Some((status, headers)))
+    }
+
+    // Header values aren't always UTF-8, and only a few of them matter here
+    fn read_header_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        if self.body.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&line).trim_end().to_string()))
+    }
+
+    // Why curl stopped, from its own message
+    fn curl_error(&mut self) -> io::Error {
+        let mut message = String::new();
+        if let Some(stderr) = self.curl.stderr.as_mut() {
+            stderr.read_to_string(&mut message).ok();
+        }
+        let status = self.curl.wait();
+        let message = message.trim().trim_start_matches("curl: ");
+        if !message.is_empty() {
+            io::Error::other(format!("download failed: {}", message))
+        } else {
+            match status {
+                Ok(status) => io::Error::other(format!("download failed: curl {}", status)),
+                Err(e) => e,
+            }
+        }
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished {
+            return Ok(0);
+        }
+        let n = self.body.read(buf)?;
+        if n == 0 {
+            // A connection dropped halfway ends the output early too; only curl knows
+            let succeeded = self.curl.wait()?.success();
+            if !succeeded {
+                return Err(self.curl_error());
+            }
+            if let Some(size) = self.size.filter(|&size| self.received < size) {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("download ended after {} of {} bytes", self.received, size),
+                ));
+            }
+            self.finished = true;
+            return Ok(0);
+        }
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        self.received += n as u64;
+        Ok(n)
+    }
+}
+
+impl Drop for Download {
+    // A download cut short by --partial or an error shouldn't keep running
+    fn drop(&mut self) {
+        if !self.finished {
+            self.curl.kill().ok();
+        }
+        self.curl.wait().ok();
+    }
+}
+
+fn header<'a>(headers: &'a [String], name: &str) -> Option<&'a str> {
+    headers.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
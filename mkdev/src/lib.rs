@@ -0,0 +1,1179 @@
+// The imaging engine behind the mkdev binary: a buffered copy loop with progress callbacks,
+// read-back verification, and the source decoding helpers mkdev uses. Nothing here touches
+// devices directly, so it works with any `Read`/`Write` pair.
+
+pub mod bmap;
+
+use flate2::read::MultiGzDecoder;
+use md5::Md5;
+use sha2::digest::{DynDigest, InvalidBufferSize};
+use sha2::Sha256;
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use xz2::read::XzDecoder;
+
+// Default to 16MB buffer - good balance for modern USB 3.0+ drives
+pub const DEFAULT_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+// O_DIRECT needs the buffer address, file offset and transfer length aligned to the logical
+// block size. 4KB covers both 512-byte and 4K-sector devices and matches the page size.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+// How often the copy and verify loops report progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often LineReporter prints a line
+const PROGRESS_LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Heap buffer whose address and length are both multiples of DIRECT_IO_ALIGNMENT.
+// `vec![0u8; n]` only guarantees byte alignment, which O_DIRECT rejects with EINVAL.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// The buffer owns its allocation outright, like a Vec<u8>
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    pub fn new(len: usize) -> AlignedBuffer {
+        let len = len.max(1).div_ceil(DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT;
+        let layout =
+            Layout::from_size_align(len, DIRECT_IO_ALIGNMENT).expect("invalid buffer size");
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        AlignedBuffer { ptr, layout }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1F, 0x8B]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChecksumAlgo {
+    Sha256,
+    Md5,
+    // Not cryptographic, but many times faster: enough to tell whether a write came back
+    // intact, not whether an image was tampered with
+    Crc32,
+}
+
+impl ChecksumAlgo {
+    // As given to --checksum-algo, in any case
+    pub fn from_name(name: &str) -> Option<ChecksumAlgo> {
+        match name.to_lowercase().as_str() {
+            "sha256" => Some(ChecksumAlgo::Sha256),
+            "md5" => Some(ChecksumAlgo::Md5),
+            "crc32" => Some(ChecksumAlgo::Crc32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgo::Sha256 => "SHA256",
+            ChecksumAlgo::Md5 => "MD5",
+            ChecksumAlgo::Crc32 => "CRC32",
+        }
+    }
+
+    pub fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgo::Sha256 => 64,
+            ChecksumAlgo::Md5 => 32,
+            ChecksumAlgo::Crc32 => 8,
+        }
+    }
+
+    pub fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            ChecksumAlgo::Sha256 => Box::new(Sha256::default()),
+            ChecksumAlgo::Md5 => Box::new(Md5::default()),
+            ChecksumAlgo::Crc32 => Box::new(Crc32::default()),
+        }
+    }
+}
+
+// crc32fast behind the same interface as the digests, big-endian like cksum-style tools
+// print it
+#[derive(Clone, Default)]
+struct Crc32(crc32fast::Hasher);
+
+impl DynDigest for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize_into(mut self, buf: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        self.finalize_into_reset(buf)
+    }
+
+    fn finalize_into_reset(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        if out.len() != 4 {
+            return Err(InvalidBufferSize);
+        }
+        out.copy_from_slice(&self.0.clone().finalize().to_be_bytes());
+        self.0.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_size(&self) -> usize {
+        4
+    }
+
+    fn box_clone(&self) -> Box<dyn DynDigest> {
+        Box::new(self.clone())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Hashes everything read through it, so a stream's digest comes out of the same pass that
+// copies it
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Box<dyn DynDigest>,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, algo: ChecksumAlgo) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: algo.hasher(),
+        }
+    }
+
+    // The hex digest of what has been read so far
+    pub fn digest(&mut self) -> String {
+        to_hex(&self.hasher.finalize_reset())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// One or more files read back to back as a single source, for images split into parts
+// (disk.img.001, disk.img.002, ...). A plain image file is a source of one part.
+pub struct SourceFile {
+    // Each part with its length when opened
+    parts: Vec<(File, u64)>,
+    position: u64,
+    // The part whose file position matches `position`, if any; any other is sought first
+    current: Option<usize>,
+}
+
+impl SourceFile {
+    pub fn new(files: Vec<File>) -> io::Result<SourceFile> {
+        let parts = files
+            .into_iter()
+            .map(|file| {
+                let len = file.metadata()?.len();
+                Ok((file, len))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(SourceFile {
+            parts,
+            position: 0,
+            current: None,
+        })
+    }
+
+    // The combined length of the parts
+    pub fn size(&self) -> u64 {
+        self.parts.iter().map(|(_, len)| len).sum()
+    }
+
+    // The part holding `position`, and the offset into it
+    fn locate(&self, position: u64) -> Option<(usize, u64)> {
+        let mut start = 0;
+        for (index, (_, len)) in self.parts.iter().enumerate() {
+            if position < start + len {
+                return Some((index, position - start));
+            }
+            start += len;
+        }
+        None
+    }
+}
+
+impl Read for SourceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some((index, offset)) = self.locate(self.position) else {
+            return Ok(0);
+        };
+        let (file, len) = &mut self.parts[index];
+        if self.current != Some(index) {
+            file.seek(SeekFrom::Start(offset))?;
+            self.current = Some(index);
+        }
+        // Stop at the end of the part, even if it has grown since, so the next read moves on
+        let available = (*len - offset).min(buf.len() as u64) as usize;
+        let n = file.read(&mut buf[..available])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "a part of the source got shorter while it was being read",
+            ));
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SourceFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative offset",
+            ));
+        };
+        self.position = position;
+        self.current = None;
+        Ok(position)
+    }
+}
+
+// Sniff the magic bytes to see whether the source is a compressed image
+pub fn detect_compression<R: Read + Seek + ?Sized>(
+    source: &mut R,
+) -> io::Result<Option<Compression>> {
+    let mut magic = [0u8; 6];
+    let mut filled = 0;
+    while filled < magic.len() {
+        let n = source.read(&mut magic[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    source.seek(SeekFrom::Start(0))?;
+    Ok(Compression::detect(&magic[..filled]))
+}
+
+// Open the source for reading from `skip` bytes into the (decompressed) image
+pub fn open_source_at<'a, R: Read + Seek + 'a>(
+    source: &'a mut R,
+    compression: Option<Compression>,
+    skip: u64,
+) -> io::Result<Box<dyn Read + 'a>> {
+    if compression.is_none() {
+        source.seek(SeekFrom::Start(skip))?;
+        return open_source_reader(source, None);
+    }
+    source.seek(SeekFrom::Start(0))?;
+    let mut reader = open_source_reader(source, compression)?;
+    skip_source(&mut reader, skip)?;
+    Ok(reader)
+}
+
+// Read and discard `skip` bytes, for sources that can't seek
+pub fn skip_source<R: Read + ?Sized>(source: &mut R, skip: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut source.take(skip), &mut io::sink())?;
+    if skipped < skip {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "source ended after {} bytes, before the --skip offset",
+                skipped
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn open_source_reader<'a, R: Read + 'a>(
+    source: R,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        None => Box::new(source),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(source)),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(source)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(source)?),
+    })
+}
+
+// Parse a size like "4096", "512K", "16M" or "2G". Suffixes are powers of 1024; a bare
+// number is bytes. "16MB" and "16mb" are rejected since it's unclear whether they mean 10^6.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b'K') => (&value[..value.len() - 1], 1u64 << 10),
+        Some(b'M') => (&value[..value.len() - 1], 1u64 << 20),
+        Some(b'G') => (&value[..value.len() - 1], 1u64 << 30),
+        _ => (value, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!(
+            "'{}' is not a size; use a number of bytes or a K, M or G suffix (e.g. 512K, 16M)",
+            value
+        ));
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("'{}' is too large", value))
+}
+
+// Parse a duration like "90", "30s", "5m" or "1h"; a bare number is seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b's') => (&value[..value.len() - 1], 1),
+        Some(b'm') => (&value[..value.len() - 1], 60),
+        Some(b'h') => (&value[..value.len() - 1], 60 * 60),
+        _ => (value, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!(
+            "'{}' is not a duration; use a number of seconds or an s, m or h suffix (e.g. 30s, 5m)",
+            value
+        ));
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too large", value))
+}
+
+// Fill the buffer as far as the source allows, so every chunk except the last is full-sized.
+// Decompressors in particular hand back data in small, irregular pieces.
+pub fn read_full<R: Read + ?Sized>(source: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match source.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// A snapshot of a copy or verify pass, handed to the progress callback about every 100ms
+// and once more with `done` set when the pass completes
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    pub bytes_written: u64,
+    // None when the size isn't known up front (stdin, decompressed sources)
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+    // Time spent in read vs write, to tell whether the source or the target is the bottleneck
+    pub read_time: Duration,
+    pub write_time: Duration,
+    // Smoothed speed over the last few seconds in MB/s, from a SpeedEstimator. None leaves
+    // the live speed and ETA to the average.
+    pub recent_speed_mbps: Option<f64>,
+    pub done: bool,
+    // Set on the last update for a target that failed partway, with what went wrong
+    pub error: Option<String>,
+}
+
+impl Progress {
+    pub fn percent(&self) -> Option<f64> {
+        self.total.map(|total| {
+            if total > 0 {
+                self.bytes_written as f64 / total as f64 * 100.0
+            } else {
+                100.0
+            }
+        })
+    }
+
+    // Average speed over the whole pass, in MB/s
+    pub fn speed_mbps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_written as f64 / secs / 1_000_000.0
+        } else {
+            0.0
+        }
+    }
+
+    // The speed to show while the pass is running
+    pub fn current_speed_mbps(&self) -> f64 {
+        self.recent_speed_mbps.unwrap_or_else(|| self.speed_mbps())
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        let speed = self.current_speed_mbps() * 1_000_000.0;
+        if speed <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            total.saturating_sub(self.bytes_written) as f64 / speed,
+        ))
+    }
+}
+
+// How quickly the smoothed speed follows a change in the real one: after this long, about
+// two thirds of the way
+const SPEED_SMOOTHING: Duration = Duration::from_secs(3);
+
+// Exponentially weighted moving average of a transfer's speed. USB sticks take writes into
+// their cache at full speed and then stall while it drains, so the speed over the last
+// interval swings wildly and the average since the start reacts too slowly; this follows
+// the trend over the last few seconds. Samples can come at any interval, since each is
+// weighted by how much time it covers.
+#[derive(Clone, Debug, Default)]
+pub struct SpeedEstimator {
+    last: Option<(u64, Duration)>,
+    // Bytes per second
+    rate: Option<f64>,
+}
+
+impl SpeedEstimator {
+    pub fn new() -> SpeedEstimator {
+        SpeedEstimator::default()
+    }
+
+    // Feed in the bytes transferred so far and the time taken; returns the smoothed speed in
+    // MB/s, or None until two samples have some time between them
+    pub fn sample(&mut self, bytes: u64, elapsed: Duration) -> Option<f64> {
+        let (last_bytes, last_elapsed) = *self.last.get_or_insert((bytes, elapsed));
+        let interval = elapsed.saturating_sub(last_elapsed).as_secs_f64();
+        if interval > 0.0 {
+            let speed = bytes.saturating_sub(last_bytes) as f64 / interval;
+            let weight = 1.0 - (-interval / SPEED_SMOOTHING.as_secs_f64()).exp();
+            self.rate = Some(match self.rate {
+                Some(rate) => rate + weight * (speed - rate),
+                None => speed,
+            });
+            self.last = Some((bytes, elapsed));
+        }
+        self.speed_mbps()
+    }
+
+    pub fn speed_mbps(&self) -> Option<f64> {
+        self.rate.map(|rate| rate / 1_000_000.0)
+    }
+}
+
+// How much time the samples behind each SpeedRange speed span. The 100ms between progress
+// updates is too short: a stick taking a burst into its cache would set the maximum.
+const SPEED_RANGE_WINDOW: Duration = Duration::from_secs(1);
+
+// The slowest and fastest a pass went over any one second, for a summary once it is done
+#[derive(Clone, Debug, Default)]
+pub struct SpeedRange {
+    window_start: (u64, Duration),
+    // MB/s
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl SpeedRange {
+    pub fn new() -> SpeedRange {
+        SpeedRange::default()
+    }
+
+    // Feed in the bytes transferred so far and the time taken, as for SpeedEstimator
+    pub fn sample(&mut self, bytes: u64, elapsed: Duration) {
+        let (start_bytes, start) = self.window_start;
+        let span = elapsed.saturating_sub(start);
+        if span < SPEED_RANGE_WINDOW {
+            return;
+        }
+        let speed = bytes.saturating_sub(start_bytes) as f64 / span.as_secs_f64() / 1_000_000.0;
+        self.min = Some(self.min.map_or(speed, |min| min.min(speed)));
+        self.max = Some(self.max.map_or(speed, |max| max.max(speed)));
+        self.window_start = (bytes, elapsed);
+    }
+
+    // (slowest, fastest) in MB/s, or None for a pass shorter than a second
+    pub fn range_mbps(&self) -> Option<(f64, f64)> {
+        Some((self.min?, self.max?))
+    }
+}
+
+// Whole seconds as "45s", "2m05s" or "1h30m", for ETAs and elapsed times
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+// Receives progress from Writer::copy and Writer::verify: `update` about every 100ms and
+// once more with `done` set, then `finish` when the pass is over
+pub trait ProgressReporter {
+    fn update(&mut self, progress: &Progress);
+    fn finish(&mut self);
+}
+
+// Any `FnMut(&Progress)` works as a reporter with nothing to do at the end
+impl<F: FnMut(&Progress)> ProgressReporter for F {
+    fn update(&mut self, progress: &Progress) {
+        self(progress)
+    }
+
+    fn finish(&mut self) {}
+}
+
+// `\r`-updated progress line on stdout, e.g.
+// "📝 Progress: 41.9% | 2.10/5.00 MB | Speed: 19.98 MB/s | ETA: 2m05s"
+pub struct TtyReporter {
+    label: String,
+    row: usize,
+    rows: usize,
+    escapes: bool,
+}
+
+impl TtyReporter {
+    pub fn new(label: &str) -> TtyReporter {
+        TtyReporter::for_row(label, 0, 1)
+    }
+
+    // One of `rows` lines drawn together, e.g. one per device being written. The caller
+    // reserves the lines by printing `rows` newlines first; each reporter then redraws its
+    // own line in place and leaves the cursor below the block.
+    pub fn for_row(label: &str, row: usize, rows: usize) -> TtyReporter {
+        TtyReporter {
+            label: label.to_string(),
+            row,
+            rows,
+            escapes: true,
+        }
+    }
+
+    // Redrawing a block of rows takes ANSI cursor movement. Without it, each row is printed
+    // once, as a line of its own, when its pass completes; a single row still uses `\r`.
+    pub fn without_escapes(mut self) -> TtyReporter {
+        self.escapes = false;
+        self
+    }
+
+    fn draw(&self, line: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        if self.rows <= 1 {
+            write!(stdout, "\r{}   ", line)?;
+        } else if !self.escapes {
+            writeln!(stdout, "{}", line)?;
+        } else {
+            let up = self.rows - self.row;
+            write!(stdout, "\x1b[{}A\r{}\x1b[K\x1b[{}B\r", up, line, up)?;
+        }
+        stdout.flush()
+    }
+}
+
+impl ProgressReporter for TtyReporter {
+    fn update(&mut self, progress: &Progress) {
+        // A failed pass's line stops where it was; the failure is reported separately
+        if progress.error.is_some() || (self.rows > 1 && !self.escapes && !progress.done) {
+            return;
+        }
+        // A broken progress display shouldn't abort the write
+        self.draw(&progress_line(&self.label, progress)).ok();
+    }
+
+    // End the progress line; a block of rows already leaves the cursor below it
+    fn finish(&mut self) {
+        if self.rows <= 1 {
+            println!();
+        }
+    }
+}
+
+// The line TtyReporter and LineReporter show for a pass
+fn progress_line(label: &str, progress: &Progress) -> String {
+    let written = progress.bytes_written as f64 / 1_000_000.0;
+    if progress.done {
+        format!(
+            "{}: 100.0% | {:.2}/{:.2} MB | Avg Speed: {:.2} MB/s | Time: {}",
+            label,
+            written,
+            progress.total.unwrap_or(progress.bytes_written) as f64 / 1_000_000.0,
+            progress.speed_mbps(),
+            format_duration(progress.elapsed)
+        )
+    } else if let Some(total) = progress.total {
+        format!(
+            "{}: {:.1}% | {:.2}/{:.2} MB | Speed: {:.2} MB/s | ETA: {}",
+            label,
+            progress.percent().unwrap_or(0.0),
+            written,
+            total as f64 / 1_000_000.0,
+            progress.current_speed_mbps(),
+            format_duration(progress.eta().unwrap_or_default())
+        )
+    } else {
+        format!(
+            "{}: {:.2} MB written | Speed: {:.2} MB/s",
+            label,
+            written,
+            progress.current_speed_mbps()
+        )
+    }
+}
+
+// A fresh progress line on stdout every few seconds and one when the pass completes, for
+// output going to a file or pipe, where `\r` redraws pile up into an unreadable mess
+pub struct LineReporter {
+    label: String,
+    last_line: Option<Instant>,
+}
+
+impl LineReporter {
+    pub fn new(label: &str) -> LineReporter {
+        LineReporter {
+            label: label.to_string(),
+            last_line: None,
+        }
+    }
+}
+
+impl ProgressReporter for LineReporter {
+    fn update(&mut self, progress: &Progress) {
+        // The first update comes almost immediately, with nothing written yet
+        let last_line = *self.last_line.get_or_insert_with(Instant::now);
+        if progress.error.is_some()
+            || (!progress.done && last_line.elapsed() < PROGRESS_LINE_INTERVAL)
+        {
+            return;
+        }
+        println!("{}", progress_line(&self.label, progress));
+        self.last_line = Some(Instant::now());
+    }
+
+    fn finish(&mut self) {}
+}
+
+// One JSON object per update on stderr, e.g.
+// {"target":"/dev/sdb","bytes_written":1048576,"total":4194304,"percent":25.0,"speed_mbps":12.5,"eta_s":0,"done":false}
+// `target` and `phase` are only present when set, and `error` only on the last update of a
+// pass that failed; `total`, `percent` and `eta_s` are null when the size isn't known up
+// front.
+#[derive(Default)]
+pub struct JsonReporter {
+    target: Option<String>,
+    phase: Option<String>,
+}
+
+impl JsonReporter {
+    pub fn new() -> JsonReporter {
+        JsonReporter::default()
+    }
+
+    pub fn with_target(mut self, target: &str) -> JsonReporter {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    // What the pass is when it isn't the write itself, e.g. "verify"
+    pub fn with_phase(mut self, phase: &str) -> JsonReporter {
+        self.phase = Some(phase.to_string());
+        self
+    }
+}
+
+impl ProgressReporter for JsonReporter {
+    fn update(&mut self, progress: &Progress) {
+        let field = |name: &str, value: &Option<String>| match value {
+            Some(value) => format!("\"{}\":{},", name, json_string(value)),
+            None => String::new(),
+        };
+        let error = match &progress.error {
+            Some(error) => format!(",\"error\":{}", json_string(error)),
+            None => String::new(),
+        };
+        let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        eprintln!(
+            "{{{}{}\"bytes_written\":{},\"total\":{},\"percent\":{},\"speed_mbps\":{:.2},\"eta_s\":{},\"done\":{}{}}}",
+            field("target", &self.target),
+            field("phase", &self.phase),
+            progress.bytes_written,
+            or_null(progress.total.map(|total| total.to_string())),
+            or_null(progress.percent().map(|percent| format!("{:.1}", percent))),
+            progress.speed_mbps(),
+            or_null(progress.eta().map(|eta| eta.as_secs().to_string())),
+            progress.done,
+            error
+        );
+    }
+
+    fn finish(&mut self) {}
+}
+
+// A JSON string literal, quotes included
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Discards all progress
+pub struct NullReporter;
+
+impl ProgressReporter for NullReporter {
+    fn update(&mut self, _progress: &Progress) {}
+
+    fn finish(&mut self) {}
+}
+
+// Copies a source to a destination a buffer at a time, reporting progress as it goes
+pub struct Writer<'a> {
+    buffer_size: usize,
+    max_rate: Option<f64>,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl Default for Writer<'_> {
+    fn default() -> Self {
+        Writer::new()
+    }
+}
+
+impl<'a> Writer<'a> {
+    pub fn new() -> Writer<'a> {
+        Writer {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_rate: None,
+            cancel: None,
+        }
+    }
+
+    // The buffer is block-aligned, so a destination opened with O_DIRECT can take it as is
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Writer<'a> {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    // Cap the average throughput, in bytes per second
+    pub fn with_max_rate(mut self, max_rate: Option<f64>) -> Writer<'a> {
+        self.max_rate = max_rate;
+        self
+    }
+
+    // Stop at the next buffer boundary once `cancel` is set, e.g. from a signal handler.
+    // A read the signal interrupts stops there too, and what it got is still written. The
+    // destination is then flushed, and `copy` fails with ErrorKind::Interrupted.
+    pub fn with_cancel_flag(mut self, cancel: &'a AtomicBool) -> Writer<'a> {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    // read_full, except that an interrupted read gives up once cancelled rather than
+    // blocking again
+    fn read_chunk<R: Read + ?Sized>(&self, src: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match src.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted && self.cancelled() => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    // Copy `src` to `dst` until the source ends, then flush `dst`. `total` is only used for
+    // progress reporting. Returns the number of bytes copied.
+    pub fn copy<R, W, P>(
+        &self,
+        src: &mut R,
+        dst: &mut W,
+        total: Option<u64>,
+        reporter: &mut P,
+    ) -> io::Result<u64>
+    where
+        R: Read + ?Sized,
+        W: Write + ?Sized,
+        P: ProgressReporter + ?Sized,
+    {
+        let mut buffer = AlignedBuffer::new(self.buffer_size);
+        let start_time = Instant::now();
+        let mut last_update = Instant::now();
+        let mut progress = Progress {
+            bytes_written: 0,
+            total,
+            elapsed: Duration::ZERO,
+            read_time: Duration::ZERO,
+            write_time: Duration::ZERO,
+            recent_speed_mbps: None,
+            done: false,
+            error: None,
+        };
+        let mut speed = SpeedEstimator::new();
+
+        loop {
+            if self.cancelled() {
+                dst.flush()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    format!(
+                        "{} bytes ({:.2} MB) were flushed to the destination before stopping",
+                        progress.bytes_written,
+                        progress.bytes_written as f64 / 1_000_000.0
+                    ),
+                ));
+            }
+
+            let read_start = Instant::now();
+            let bytes_read = self.read_chunk(src, &mut buffer)?;
+            progress.read_time += read_start.elapsed();
+            if bytes_read == 0 {
+                // Interrupted before anything was read; stop at the top of the loop
+                if self.cancelled() {
+                    continue;
+                }
+                break;
+            }
+
+            let write_start = Instant::now();
+            dst.write_all(&buffer[..bytes_read])?;
+            progress.write_time += write_start.elapsed();
+            progress.bytes_written += bytes_read as u64;
+
+            // Update progress every 100ms. Under a rate cap, wait until the bytes written so
+            // far are due, waking often enough to keep the display ticking.
+            loop {
+                if last_update.elapsed() >= PROGRESS_INTERVAL {
+                    progress.elapsed = start_time.elapsed();
+                    progress.recent_speed_mbps =
+                        speed.sample(progress.bytes_written, progress.elapsed);
+                    reporter.update(&progress);
+                    last_update = Instant::now();
+                }
+
+                let Some(rate) = self.max_rate else {
+                    break;
+                };
+                let due = Duration::from_secs_f64(progress.bytes_written as f64 / rate);
+                let elapsed = start_time.elapsed();
+                if elapsed >= due || self.cancelled() {
+                    break;
+                }
+                thread::sleep((due - elapsed).min(PROGRESS_INTERVAL));
+            }
+        }
+
+        let flush_start = Instant::now();
+        dst.flush()?;
+        progress.write_time += flush_start.elapsed();
+
+        progress.elapsed = start_time.elapsed();
+        progress.done = true;
+        reporter.update(&progress);
+        reporter.finish();
+
+        Ok(progress.bytes_written)
+    }
+
+    // Compare the first `total` bytes of `dst` against `src` and return the offset of the
+    // first byte that differs, if any. When a hasher is given, the bytes read back from
+    // `dst` are fed into it as well.
+    pub fn verify<R, T, P>(
+        &self,
+        src: &mut R,
+        dst: &mut T,
+        total: u64,
+        mut hasher: Option<&mut Box<dyn DynDigest>>,
+        reporter: &mut P,
+    ) -> io::Result<Option<u64>>
+    where
+        R: Read + ?Sized,
+        T: Read + ?Sized,
+        P: ProgressReporter + ?Sized,
+    {
+        let mut source_buffer = vec![0u8; self.buffer_size];
+        let mut target_buffer = vec![0u8; self.buffer_size];
+
+        let start_time = Instant::now();
+        let mut last_update = Instant::now();
+        let mut progress = Progress {
+            bytes_written: 0,
+            total: Some(total),
+            elapsed: Duration::ZERO,
+            read_time: Duration::ZERO,
+            write_time: Duration::ZERO,
+            recent_speed_mbps: None,
+            done: false,
+            error: None,
+        };
+        let mut speed = SpeedEstimator::new();
+
+        while progress.bytes_written < total {
+            let to_read = self
+                .buffer_size
+                .min((total - progress.bytes_written) as usize);
+            let read_start = Instant::now();
+            src.read_exact(&mut source_buffer[..to_read])?;
+            dst.read_exact(&mut target_buffer[..to_read])?;
+            progress.read_time += read_start.elapsed();
+
+            if let Some(index) = source_buffer[..to_read]
+                .iter()
+                .zip(&target_buffer[..to_read])
+                .position(|(a, b)| a != b)
+            {
+                reporter.finish();
+                return Ok(Some(progress.bytes_written + index as u64));
+            }
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&target_buffer[..to_read]);
+            }
+            progress.bytes_written += to_read as u64;
+
+            if last_update.elapsed() >= PROGRESS_INTERVAL {
+                progress.elapsed = start_time.elapsed();
+                progress.recent_speed_mbps = speed.sample(progress.bytes_written, progress.elapsed);
+                reporter.update(&progress);
+                last_update = Instant::now();
+            }
+        }
+
+        progress.elapsed = start_time.elapsed();
+        progress.done = true;
+        reporter.update(&progress);
+        reporter.finish();
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_buffer_is_aligned() {
+        for len in [1, 512, 4096, 4097, 65536, DEFAULT_BUFFER_SIZE] {
+            let buffer = AlignedBuffer::new(len);
+            assert_eq!(
+                buffer.as_ptr() as usize % DIRECT_IO_ALIGNMENT,
+                0,
+                "len {}",
+                len
+            );
+            assert_eq!(
+                buffer.len(),
+                len.div_ceil(DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT
+            );
+            assert!(buffer.iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn aligned_buffer_of_zero_is_one_block() {
+        let buffer = AlignedBuffer::new(0);
+        assert_eq!(buffer.len(), DIRECT_IO_ALIGNMENT);
+        assert_eq!(buffer.as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+    }
+
+    #[test]
+    fn parse_size_suffixes() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("16M"), Ok(16 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("0"), Ok(0));
+    }
+
+    #[test]
+    fn parse_size_rejects_ambiguous_and_invalid() {
+        for value in [
+            "16MB", "16mb", "16m", "", "M", "-1", "-16M", "1.5M", " 16M", "16 M", "abc",
+        ] {
+            assert!(parse_size(value).is_err(), "'{}' was accepted", value);
+        }
+        assert!(parse_size("99999999999999999999").is_err());
+        assert!(parse_size("17179869184G").is_err());
+    }
+
+    #[test]
+    fn format_duration_boundaries() {
+        let format = |secs| format_duration(Duration::from_secs(secs));
+        assert_eq!(format(0), "0s");
+        assert_eq!(format(59), "59s");
+        assert_eq!(format(60), "1m00s");
+        assert_eq!(format(125), "2m05s");
+        assert_eq!(format(3599), "59m59s");
+        assert_eq!(format(3600), "1h00m");
+        assert_eq!(format(5400), "1h30m");
+        // Part seconds are dropped, not rounded
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "59s");
+    }
+
+    // Bytes transferred by each 100ms tick up to `ticks`, at `mbps(tick)` MB/s during it
+    fn samples(ticks: u64, mbps: impl Fn(u64) -> u64) -> Vec<(u64, Duration)> {
+        let mut bytes = 0;
+        (0..=ticks)
+            .map(|tick| {
+                if tick > 0 {
+                    bytes += mbps(tick) * 100_000;
+                }
+                (bytes, Duration::from_millis(tick * 100))
+            })
+            .collect()
+    }
+
+    fn estimate(samples: &[(u64, Duration)]) -> (Option<f64>, Option<(f64, f64)>) {
+        let mut speed = SpeedEstimator::new();
+        let mut range = SpeedRange::new();
+        let mut estimate = None;
+        for &(bytes, elapsed) in samples {
+            estimate = speed.sample(bytes, elapsed);
+            range.sample(bytes, elapsed);
+        }
+        (estimate, range.range_mbps())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn speed_needs_two_samples() {
+        let mut speed = SpeedEstimator::new();
+        assert_eq!(speed.sample(0, Duration::ZERO), None);
+        // No time has passed, so these bytes count towards the next interval
+        assert_eq!(speed.sample(1_000_000, Duration::ZERO), None);
+        assert_close(
+            speed.sample(2_000_000, Duration::from_secs(1)).unwrap(),
+            2.0,
+        );
+    }
+
+    #[test]
+    fn speed_at_a_steady_rate() {
+        let (speed, range) = estimate(&samples(100, |_| 10));
+        assert_close(speed.unwrap(), 10.0);
+        let (min, max) = range.unwrap();
+        assert_close(min, 10.0);
+        assert_close(max, 10.0);
+    }
+
+    #[test]
+    fn speed_follows_a_step_change() {
+        // 10 MB/s for 10s, then 20 MB/s for SPEED_SMOOTHING (3s): two thirds of the way there
+        let (speed, range) = estimate(&samples(130, |tick| if tick <= 100 { 10 } else { 20 }));
+        assert_close(speed.unwrap(), 20.0 - 10.0 * (-1.0f64).exp());
+        let (min, max) = range.unwrap();
+        assert_close(min, 10.0);
+        assert_close(max, 20.0);
+
+        // And settles on the new rate
+        let (speed, _) = estimate(&samples(400, |tick| if tick <= 100 { 10 } else { 20 }));
+        assert_close(speed.unwrap(), 20.0);
+    }
+
+    #[test]
+    fn speed_decays_through_a_stall() {
+        // 10 MB/s for 10s, then nothing for 3s while a stick drains its cache
+        let (speed, range) = estimate(&samples(130, |tick| if tick <= 100 { 10 } else { 0 }));
+        assert_close(speed.unwrap(), 10.0 * (-1.0f64).exp());
+        let (min, max) = range.unwrap();
+        assert_close(min, 0.0);
+        assert_close(max, 10.0);
+    }
+
+    #[test]
+    fn speed_range_needs_a_second() {
+        let (speed, range) = estimate(&samples(9, |_| 10));
+        assert_close(speed.unwrap(), 10.0);
+        assert_eq!(range, None);
+    }
+}
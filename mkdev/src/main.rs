@@ -1,274 +1,3292 @@
+mod download;
+mod notify;
+mod oplog;
+mod platform;
+mod resume;
+
+use args::completions::{self, Command, Complete, Shell};
+use args::Opt;
+use download::Download;
+use mkdev::bmap::{BlockMap, MappedReader};
+use mkdev::{
+    detect_compression, format_duration, json_string, open_source_at, open_source_reader,
+    parse_duration, parse_size, read_full, skip_source, to_hex, AlignedBuffer, ChecksumAlgo,
+    Compression, HashingReader, JsonReporter, LineReporter, NullReporter, Progress,
+    ProgressReporter, SourceFile, SpeedRange, TtyReporter, Writer, DEFAULT_BUFFER_SIZE,
+    DIRECT_IO_ALIGNMENT,
+};
+use oplog::{Entry, OperationLog};
+use output::{say, warn};
+use platform::{Platform, System};
+use resume::Checkpoint;
 use std::env;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::os::unix::fs::OpenOptionsExt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
 use std::process;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Exit statuses, one for each kind of failure so scripts can tell them apart
+#[derive(Clone, Copy)]
+enum Exit {
+    // Bad options or arguments
+    Usage = 2,
+    // The source, or a file that goes with it, can't be read or isn't fit to write
+    Source = 3,
+    // A target can't be opened, or is refused as unsafe or too small
+    Target = 4,
+    // Writing (or wiping) a target failed
+    Write = 5,
+    // A target doesn't read back as the source
+    Mismatch = 6,
+    // The answer at the confirmation prompt wasn't yes
+    Cancelled = 7,
+    // A write made no progress for --timeout
+    Stalled = 8,
+    // SIGINT/SIGTERM stopped a write (128 + SIGINT, as shells report it)
+    Interrupted = 130,
+}
+
+impl Exit {
+    fn exit(self) -> ! {
+        notify::send(Some(self.outcome()));
+        process::exit(self as i32)
+    }
+
+    // What a --notify notification says went wrong
+    fn outcome(self) -> &'static str {
+        match self {
+            Exit::Usage => "failed",
+            Exit::Source => "source check failed",
+            Exit::Target => "target refused",
+            Exit::Write => "write failed",
+            Exit::Mismatch => "verification failed",
+            Exit::Cancelled => "cancelled",
+            Exit::Stalled => "write stalled",
+            Exit::Interrupted => "write interrupted",
+        }
+    }
+}
+
+// Set by the interrupt handler and polled once per buffer in the copy loop
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// --timeout: the bytes of the image written so far, which the watchdog checks for movement,
+// and whether it found none for that long. A stall stops the copy the way an interrupt does.
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+static STALLED: AtomicBool = AtomicBool::new(false);
 
-// Default to 16MB buffer - good balance for modern USB 3.0+ drives
-const DEFAULT_BUFFER_SIZE: usize = 16 * 1024 * 1024;
 const BENCHMARK_DATA_SIZE: usize = 64 * 1024 * 1024; // 64MB for benchmark
 
+// Buffer sizes the benchmark tries: 2MB, 4MB, 8MB, 16MB, 32MB, 64MB
+const BENCHMARK_BUFFER_SIZES: [usize; 6] = [
+    2 * 1024 * 1024,
+    4 * 1024 * 1024,
+    8 * 1024 * 1024,
+    16 * 1024 * 1024,
+    32 * 1024 * 1024,
+    64 * 1024 * 1024,
+];
+
+// ISO 9660 sectors are 2KB; the El Torito boot record lives in the volume descriptor at sector 17
+const ISO_SECTOR_SIZE: u64 = 2048;
+const EL_TORITO_SECTOR: u64 = 17;
+
+// How much of the image is inspected for a partition table or filesystem signature
+const SIGNATURE_PROBE_SIZE: usize = 64 * 1024;
+
+// The flush spinner waits this long before it appears, then redraws this often
+const FLUSH_SPINNER_DELAY: Duration = Duration::from_millis(500);
+const FLUSH_SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+const FLUSH_SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// With --drop-cache, written pages are evicted from the page cache every 256MB
+const DROP_CACHE_INTERVAL: u64 = 256 * 1024 * 1024;
+
+// Write retries for transient errors unless --retries says otherwise, backing off a little
+// longer after each failed attempt
+const DEFAULT_WRITE_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+// How often --resume flushes and records its progress when --sync-interval doesn't say
+const RESUME_SYNC_INTERVAL: u64 = 256 * 1024 * 1024;
+
+// The buffer size to use when --buffer-size isn't given, instead of auto-detecting one
+const BUFFER_SIZE_VAR: &str = "SPLASH_MKDEV_BUFFER_SIZE";
+
+// How often the --timeout watchdog looks at the progress, and how long a stalled write then
+// gets to stop by itself and flush what it has, before mkdev exits without it
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+const STALL_GRACE: Duration = Duration::from_secs(30);
+
+// Every option mkdev takes; see USAGE for what they do
+const OPTIONS: &[Opt] = &[
+    Opt::flag("help", Some('h')),
+    Opt::flag("version", None),
+    Opt::value("generate-completions", None),
+    Opt::flag("list", None).aliases(&["list-devices"]),
+    Opt::flag("benchmark", None),
+    Opt::value("buffer-size", None),
+    Opt::flag("verify", None),
+    Opt::flag("compare", None),
+    Opt::flag("yes", None).aliases(&["no-confirm"]),
+    Opt::value("sha256", None),
+    Opt::value("md5", None),
+    Opt::flag("hash-both", None),
+    Opt::value("checksum-algo", None),
+    Opt::flag("drop-cache", None),
+    Opt::value("sync-interval", None),
+    Opt::value("max-rate", None),
+    Opt::value("timeout", None),
+    Opt::value("bmap", None),
+    Opt::value("retries", None),
+    Opt::flag("wipe-remainder", None),
+    Opt::flag("sparse", None),
+    Opt::flag("concat", None),
+    Opt::value("progress", None),
+    Opt::value("seek", None),
+    Opt::flag("strict", None),
+    Opt::value("skip", None),
+    Opt::value("partial", None),
+    Opt::flag("force", None),
+    Opt::flag("partition", None),
+    Opt::flag("allow-file", None),
+    Opt::flag("no-decompress", None),
+    Opt::value("log", None),
+    Opt::value("resume", None),
+    Opt::flag("quiet", Some('q')),
+    Opt::flag("plain", None),
+    Opt::flag("notify", None),
+];
+
+// An opened target device and how it is being written
+struct Target {
+    path: String,
+    file: File,
+    offset: u64,
+    direct_io: bool,
+    drop_cache: bool,
+    // Flush to the device every this many bytes (--sync-interval) rather than only at the end
+    sync_interval: Option<u64>,
+    block_device: bool,
+    // With --sparse, how far past the offset the target is known to read back zeros, so
+    // blocks of zeros up to there need not be written
+    zeroed_until: u64,
+    // With --resume, where to record how much has been flushed to the device
+    checkpoint: Option<Checkpoint>,
+}
+
+// Where --seek starts writing: bytes from the start of the target, or with a leading `-`,
+// bytes back from its end, e.g. for a backup GPT
+#[derive(Clone, Copy)]
+enum TargetOffset {
+    Start(u64),
+    End(u64),
+}
+
+// How copy_with_progress reports progress
+#[derive(Clone, Copy, PartialEq)]
+enum ProgressFormat {
+    // `\r`-updated lines on stdout
+    Human,
+    // One JSON object per update on stderr, for front-ends
+    Json,
+    // Nothing at all, for --quiet
+    Quiet,
+}
+
+// How the final flush to the device shows that it is still going
+#[derive(Clone, Copy, PartialEq)]
+enum FlushNotice {
+    // A line redrawn in place with the time spent so far, on a terminal
+    Spinner,
+    // A single line saying the flush has started, for output going to a file or pipe
+    Line,
+    None,
+}
+
+// What verify_target needs to re-read the image
+struct VerifySource<'a> {
+    file: &'a mut SourceFile,
+    compression: Option<Compression>,
+    skip: u64,
+    bmap: Option<&'a BlockMap>,
+    // --partial: only this much of the image was written
+    partial: Option<u64>,
+}
+
+// How --wipe-remainder cleared the space after the image
+enum Wipe {
+    Discarded,
+    Zeroed,
+}
+
+// Printed to stdout for --help, and to stderr when there is nothing to do
+const USAGE: &str = "\
+Usage: mkdev <source_file> <target_device>... [options]
+       mkdev --concat <source_part>... <target_device> [options]
+       mkdev --list
+       mkdev --benchmark <target_device>
+Example: mkdev ubuntu.iso /dev/sdc
+         mkdev ubuntu.iso /dev/sdc --buffer-size 32
+         xzcat ubuntu.img.xz | mkdev - /dev/sdc
+         mkdev ubuntu.iso /dev/sdb /dev/sdc /dev/sdd
+
+By default, mkdev auto-detects the optimal buffer size for your device.
+Use - as the source to read the image from stdin. An http:// or https:// source is
+downloaded with curl as it is written.
+Several targets are written in parallel from a single read of the source.
+Options may come anywhere on the command line, before or after the paths.
+
+Options:
+  --list               List candidate target devices and exit
+  --benchmark          Measure the device's read and write speed at each buffer
+                       size, leaving its contents as they were, and exit
+  --buffer-size <size> Manually override the buffer size (MB, or 512K, 8M, 1G).
+                       SPLASH_MKDEV_BUFFER_SIZE sets one when this isn't given.
+                       Without either, writes to the first target are timed to
+                       pick one for every target
+  --verify             Read the device back and compare it to the source
+  --compare            Only compare the targets to the source, without writing
+  --yes, --no-confirm  Skip the confirmation prompt (for scripts)
+  --sha256 <hex>       Check the source (and the device with --verify) digest
+  --md5 <hex>          Same as --sha256, using MD5
+  --hash-both          Print a digest of the image as it is written and, with
+                       --verify, of each device as read back, and whether they match
+  --checksum-algo <sha256|md5|crc32>
+                       The digest --hash-both prints. Defaults to the fast CRC32
+                       with --verify, or to the algorithm of --sha256/--md5 and
+                       otherwise SHA256
+  --drop-cache         Keep the page cache from growing during long writes
+  --sync-interval <mb> Flush to the device every <mb> MB instead of at the end
+  --max-rate <mb/s>    Cap the write speed, e.g. to share a USB hub
+  --timeout <duration> Give up on a write that makes no progress for this long
+                       (seconds, or 30s, 5m, 1h), e.g. a hung USB controller. The
+                       final flush counts too, so allow for it without direct I/O
+  --bmap <file>        Write only the blocks listed in a bmaptool block map
+  --retries <n>        Retry a failed write up to n times (default: 3)
+  --wipe-remainder     Discard (or zero) the rest of the device after the image
+  --sparse             Leave out blocks of zeros. The target is discarded first (a
+                       file target is cut off at the offset) so they read back as
+                       zeros; devices that can't discard get every block written
+  --concat             Write several source files one after the other as a single
+                       image, e.g. a split disk.img.001, disk.img.002, ...
+  --progress=json      Report progress as JSON lines on stderr
+  --seek <size>        Start writing at this offset on the target (e.g. 8192, 1M),
+                       or with a leading -, this far before its end (e.g. -17K)
+  --skip <size>        Skip this many bytes at the start of the source
+  --partial <size>     Write only this much of the image (e.g. 1M for the boot area)
+  --strict             Refuse a --seek that is not a multiple of the device's block
+                       size instead of rounding it down
+  --force              Turn the safety checks into warnings: write to mounted or
+                       non-removable disks, or targets too small for the image.
+                       The confirmation prompt is still shown; see --yes
+  --partition          The target is meant to be a partition, not a whole disk
+                       (no warning about it)
+  --allow-file         Let a target be a regular file, to write an image file on
+                       purpose; otherwise only devices are written to. The file is
+                       created if missing and sized to the image
+  --no-decompress      Write gzip/xz/zstd sources verbatim instead of unpacking
+  --log <file>         Append a JSON line per target recording how the write went
+  --resume <file>      Record progress in <file> as the write goes (flushing every
+                       256MB unless --sync-interval says otherwise), and carry on
+                       from there if a failed write is run again
+  --quiet, -q          Only print warnings and errors, with no progress
+  --notify             Show a desktop notification (through notify-send) when the
+                       write finishes or fails
+  --plain              No emoji or terminal escapes (also set by NO_COLOR, or
+                       when stdout is not a terminal)
+  --help, -h           Show this help and exit
+  --generate-completions <bash|zsh|fish>
+                       Print a completion script for the shell and exit
+  --version            Show the version and exit
+
+Exit status: 0 on success, 2 for bad arguments, 3 if the source can't be read,
+4 if a target can't be opened or is refused, 5 if writing fails, 6 if a target
+doesn't match the source, 7 if cancelled at the prompt, 8 if a write stalled
+for --timeout, 130 if interrupted.
+
+Warning: This will OVERWRITE all data on the target device!
+";
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let parsed = match args::parse(OPTIONS, env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Run 'mkdev --help' for usage");
+            Exit::Usage.exit();
+        }
+    };
+
+    output::init(parsed.flag("quiet"), parsed.flag("plain"));
 
-    if args.len() < 3 {
-        eprintln!("Usage: mkdev <source_file> <target_device> [--buffer-size <size_in_mb>]");
-        eprintln!("Example: mkdev ubuntu.iso /dev/sdc");
-        eprintln!("         mkdev ubuntu.iso /dev/sdc --buffer-size 32");
-        eprintln!("\nBy default, mkdev auto-detects the optimal buffer size for your device.");
-        eprintln!("Use --buffer-size to manually override if needed.");
-        eprintln!("\nWarning: This will OVERWRITE all data on the target device!");
-        process::exit(1);
+    if parsed.flag("help") {
+        print!("{}", USAGE);
+        return;
+    }
+    if parsed.flag("version") {
+        println!("mkdev {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if let Some(shell) = parsed.value("generate-completions") {
+        print_completions(shell);
+        return;
+    }
+    if parsed.flag("list") {
+        list_devices();
+        return;
+    }
+    if parsed.flag("benchmark") {
+        if parsed.positionals.len() != 1 {
+            eprintln!("Error: Invalid arguments");
+            eprintln!("Usage: mkdev --benchmark <target_device>");
+            Exit::Usage.exit();
+        }
+        let target_path = &parsed.positionals[0];
+        refuse_file_target(target_path, parsed.flag("allow-file"));
+        refuse_unsafe_target(target_path, parsed.flag("force"));
+        if let Err(e) = benchmark_device(target_path) {
+            eprintln!("Error: Benchmark of '{}' failed: {}", target_path, e);
+            Exit::Target.exit();
+        }
+        return;
+    }
+    if parsed.positionals.len() < 2 {
+        eprint!("{}", USAGE);
+        Exit::Usage.exit();
     }
 
-    let source_path = &args[1];
-    let target_path = &args[2];
+    // With --concat every path but the last is a part of the source, and the last is the
+    // one target. Otherwise the first is the source and every other one a target.
+    let concat = parsed.flag("concat");
+    let (source_paths, target_paths) = if concat {
+        parsed.positionals.split_at(parsed.positionals.len() - 1)
+    } else {
+        parsed.positionals.split_at(1)
+    };
+    if concat
+        && source_paths
+            .iter()
+            .any(|path| path == "-" || download::is_url(path))
+    {
+        eprintln!("Error: --concat only joins source files, not stdin or URLs");
+        Exit::Usage.exit();
+    }
+    let source_label = source_paths.join(" + ");
+    let source_path = source_label.as_str();
 
-    // Parse flags
-    let mut manual_buffer_size = None;
-
-    for i in 3..args.len() {
-        if args[i] == "--buffer-size" && i + 1 < args.len() {
-            match args[i + 1].parse::<usize>() {
-                Ok(size_mb) => manual_buffer_size = Some(size_mb * 1024 * 1024),
-                Err(_) => {
-                    eprintln!("Error: Invalid buffer size. Use a number in MB (e.g., 16 for 16MB)");
-                    process::exit(1);
-                }
-            }
+    for (i, path) in target_paths.iter().enumerate() {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+        if target_paths[..i]
+            .iter()
+            .any(|other| fs::canonicalize(other).unwrap_or_else(|_| other.into()) == canonical)
+        {
+            eprintln!("Error: Target '{}' is given more than once", path);
+            Exit::Usage.exit();
         }
     }
 
-    // Confirm operation
-    println!("Source: {}", source_path);
-    println!("Target: {}", target_path);
-    println!(
-        "\n⚠️  WARNING: This will permanently erase all data on {}!",
-        target_path
-    );
-    println!("Are you sure you want to continue? (yes/no): ");
+    // Parse flags
+    let force = parsed.flag("force");
+    let partition_ok = force || parsed.flag("partition");
+    let allow_file = parsed.flag("allow-file");
+    let drop_cache = parsed.flag("drop-cache");
+    let verify = parsed.flag("verify");
+    let hash_both = parsed.flag("hash-both");
+    let compare = parsed.flag("compare");
+    let decompress = !parsed.flag("no-decompress");
+    let assume_yes = parsed.flag("yes");
+    let wipe_remainder = parsed.flag("wipe-remainder");
+    let sparse = parsed.flag("sparse");
 
-    let mut confirmation = String::new();
-    io::stdin()
-        .read_line(&mut confirmation)
-        .expect("Failed to read input");
+    // Without either, the size is auto-detected further down
+    let env_buffer_size = env::var(BUFFER_SIZE_VAR).ok();
+    let buffer_size_setting =
+        match buffer_size_setting(parsed.value("buffer-size"), env_buffer_size.as_deref()) {
+            Ok(setting) => setting,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                Exit::Usage.exit();
+            }
+        };
+    let manual_buffer_size = buffer_size_setting.map(|(size, _)| size);
 
-    if confirmation.trim().to_lowercase() != "yes" {
-        println!("Operation cancelled.");
-        process::exit(0);
-    }
+    let sync_interval = parsed
+        .value("sync-interval")
+        .map(|value| match value.parse::<u64>() {
+            Ok(mb) if mb > 0 => mb * 1024 * 1024,
+            _ => {
+                eprintln!("Error: Invalid sync interval. Use a size in MB (e.g., 256)");
+                Exit::Usage.exit();
+            }
+        });
 
-    // Open source file
-    let mut source_file = match File::open(source_path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Error: Cannot open source file '{}': {}", source_path, e);
-            process::exit(1);
+    let offset = |option: &str| match parsed.value(option).map(parse_size) {
+        None => 0,
+        Some(Ok(offset)) => offset,
+        Some(Err(e)) => {
+            eprintln!("Error: Invalid --{} offset: {}", option, e);
+            Exit::Usage.exit();
         }
     };
+    let seek = match parsed
+        .value("seek")
+        .and_then(|value| value.strip_prefix('-'))
+    {
+        Some(back) => match parse_size(back) {
+            Ok(back) => TargetOffset::End(back),
+            Err(e) => {
+                eprintln!("Error: Invalid --seek offset: {}", e);
+                Exit::Usage.exit();
+            }
+        },
+        None => TargetOffset::Start(offset("seek")),
+    };
+    let skip = offset("skip");
+    let strict = parsed.flag("strict");
+    let partial = parsed
+        .value("partial")
+        .map(|value| match parse_size(value) {
+            Ok(size) if size > 0 => size,
+            Ok(_) => {
+                eprintln!("Error: --partial needs a size greater than 0");
+                Exit::Usage.exit();
+            }
+            Err(e) => {
+                eprintln!("Error: Invalid --partial size: {}", e);
+                Exit::Usage.exit();
+            }
+        });
 
-    let source_size = match source_file.metadata() {
-        Ok(metadata) => metadata.len(),
-        Err(e) => {
-            eprintln!("Error: Cannot read source file metadata: {}", e);
-            process::exit(1);
+    let progress_format = match parsed.value("progress") {
+        None if output::quiet() => ProgressFormat::Quiet,
+        None | Some("human") => ProgressFormat::Human,
+        Some("json") => ProgressFormat::Json,
+        Some(format) => {
+            eprintln!(
+                "Error: Unknown progress format '{}'. Use human or json",
+                format
+            );
+            Exit::Usage.exit();
         }
     };
 
-    // Open target device with sync for data integrity
-    let mut target_file = match OpenOptions::new()
-        .write(true)
-        .custom_flags(libc::O_SYNC)
-        .open(target_path)
-    {
-        Ok(file) => file,
-        Err(_) => match OpenOptions::new().write(true).open(target_path) {
-            Ok(file) => file,
+    let bmap = parsed.value("bmap").map(|path| {
+        let parsed = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|xml| BlockMap::parse(&xml));
+        match parsed {
+            Ok(map) => map,
             Err(e) => {
-                eprintln!("Error: Cannot open target device '{}': {}", target_path, e);
-                eprintln!("Make sure you have permission (try sudo) and the device exists.");
-                process::exit(1);
+                eprintln!("Error: Cannot read block map '{}': {}", path, e);
+                Exit::Source.exit();
             }
-        },
+        }
+    });
+
+    let retries = match parsed.value("retries").map(str::parse::<u32>) {
+        None => DEFAULT_WRITE_RETRIES,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("Error: Invalid retry count. Use a whole number (e.g., 5)");
+            Exit::Usage.exit();
+        }
     };
 
-    println!(
-        "\n📦 Source size: {:.2} MB ({} bytes)",
-        source_size as f64 / 1_000_000.0,
-        source_size
-    );
+    let mut log = parsed
+        .value("log")
+        .map(|path| match OperationLog::open(Path::new(path)) {
+            Ok(log) => log,
+            Err(e) => {
+                eprintln!("Error: Cannot open log file '{}': {}", path, e);
+                Exit::Usage.exit();
+            }
+        });
 
-    // Determine buffer size
-    let buffer_size = if let Some(size) = manual_buffer_size {
-        println!(
-            "🔧 Using manually specified buffer size: {:.1}MB\n",
-            size as f64 / 1_048_576.0
-        );
-        size
-    } else {
-        println!("🔍 Auto-detecting optimal buffer size...");
-        match detect_optimal_buffer_size(&mut source_file, &mut target_file, source_size) {
-            Ok(size) => {
-                println!(
-                    "✅ Optimal buffer size detected: {:.1}MB\n",
-                    size as f64 / 1_048_576.0
-                );
-                size
+    let max_rate = parsed
+        .value("max-rate")
+        .map(|value| match value.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate.is_finite() => rate * 1_000_000.0,
+            _ => {
+                eprintln!("Error: Invalid max rate. Use a speed in MB/s (e.g., 10)");
+                Exit::Usage.exit();
+            }
+        });
+
+    let timeout = parsed
+        .value("timeout")
+        .map(|value| match parse_duration(value) {
+            Ok(timeout) if !timeout.is_zero() => timeout,
+            Ok(_) => {
+                eprintln!("Error: Invalid timeout. Use a duration longer than 0");
+                Exit::Usage.exit();
             }
             Err(e) => {
-                eprintln!(
-                    "⚠️  Warning: Auto-detection failed ({}), using default 16MB\n",
-                    e
-                );
-                DEFAULT_BUFFER_SIZE
+                eprintln!("Error: Invalid timeout: {}", e);
+                Exit::Usage.exit();
             }
+        });
+
+    let mut expected_checksum = None;
+    for (option, algo) in [("sha256", ChecksumAlgo::Sha256), ("md5", ChecksumAlgo::Md5)] {
+        let Some(digest) = parsed.value(option) else {
+            continue;
+        };
+        let digest = digest.to_lowercase();
+        if digest.len() != algo.hex_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            eprintln!(
+                "Error: Invalid {} digest. Expected {} hex characters",
+                algo.name(),
+                algo.hex_len()
+            );
+            Exit::Usage.exit();
         }
-    };
+        expected_checksum = Some((algo, digest));
+    }
 
-    // Reset file positions after benchmark
-    if manual_buffer_size.is_none() {
-        source_file.seek(SeekFrom::Start(0)).ok();
-        target_file.seek(SeekFrom::Start(0)).ok();
+    // stdin can be read only once, and so can a URL short of downloading it again
+    let from_stdin = source_path == "-";
+    let from_url = download::is_url(source_path);
+    if bmap.is_some() && skip > 0 {
+        eprintln!("Error: --skip cannot be combined with --bmap");
+        Exit::Usage.exit();
+    }
+    if bmap.is_some() && partial.is_some() {
+        eprintln!("Error: --partial cannot be combined with --bmap");
+        Exit::Usage.exit();
+    }
+    let resume_path = parsed.value("resume");
+    if resume_path.is_some() {
+        let conflict = if from_stdin || download::is_url(source_path) {
+            Some("a source other than a file")
+        } else if bmap.is_some() {
+            Some("--bmap")
+        } else if compare {
+            Some("--compare")
+        } else if target_paths.len() > 1 {
+            Some("more than one target")
+        } else if matches!(seek, TargetOffset::End(_)) {
+            Some("a --seek from the end")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            eprintln!("Error: --resume cannot be combined with {}", conflict);
+            Exit::Usage.exit();
+        }
     }
 
-    println!("🚀 Starting write operation...\n");
+    let checksum_algo = parsed.value("checksum-algo").map(|name| {
+        if !hash_both {
+            eprintln!("Error: --checksum-algo picks the digest for --hash-both, which isn't given");
+            Exit::Usage.exit();
+        }
+        match ChecksumAlgo::from_name(name) {
+            Some(algo) => algo,
+            None => {
+                eprintln!(
+                    "Error: Unknown checksum algorithm '{}'. Use sha256, md5 or crc32",
+                    name
+                );
+                Exit::Usage.exit();
+            }
+        }
+    });
+    // Reading back is only about whether the write came through, so the fast CRC32 will
+    // do; next to a published digest the same cryptographic one is more use
+    let digest_algo = hash_both.then(|| {
+        checksum_algo.unwrap_or(match &expected_checksum {
+            Some((algo, _)) => *algo,
+            None if verify => ChecksumAlgo::Crc32,
+            None => ChecksumAlgo::Sha256,
+        })
+    });
 
-    // Perform the copy operation
-    if let Err(e) = copy_with_progress(source_file, target_file, source_size, buffer_size) {
-        eprintln!("\n❌ Error during write operation: {}", e);
-        process::exit(1);
+    if hash_both {
+        // The digests are of the whole image as written, which these leave out parts of
+        let conflict = if bmap.is_some() {
+            Some("--bmap")
+        } else if compare {
+            Some("--compare")
+        } else if resume_path.is_some() {
+            Some("--resume")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            eprintln!("Error: --hash-both cannot be combined with {}", conflict);
+            Exit::Usage.exit();
+        }
     }
 
-    println!("\n✅ Successfully written to {}", target_path);
-}
+    if from_stdin && (verify || compare || expected_checksum.is_some()) {
+        eprintln!("Error: --verify, --compare, --sha256 and --md5 need to re-read the source,");
+        eprintln!("which is not possible when reading from stdin");
+        Exit::Usage.exit();
+    }
+    // The confirmation is read from stdin, unless the image is coming in on it; then it is
+    // asked on the terminal, and with no terminal only --yes can go ahead
+    let console = if from_stdin && !assume_yes {
+        match System.console() {
+            Ok(tty) => Some(tty),
+            Err(_) => {
+                eprintln!("Error: Reading the image from stdin requires --yes when there is no");
+                eprintln!("terminal to ask for confirmation on");
+                Exit::Usage.exit();
+            }
+        }
+    } else {
+        None
+    };
+    if from_url && (verify || compare) {
+        eprintln!("Error: --verify and --compare need to re-read the source, which is not");
+        eprintln!("possible for a URL. --sha256 and --md5 check the download as it streams.");
+        Exit::Usage.exit();
+    }
 
-fn detect_optimal_buffer_size(
-    source: &mut File,
-    _target: &mut File,
-    source_size: u64,
-) -> io::Result<usize> {
-    // Test buffer sizes: 2MB, 4MB, 8MB, 16MB, 32MB, 64MB
-    let test_sizes = vec![
-        2 * 1024 * 1024,
-        4 * 1024 * 1024,
-        8 * 1024 * 1024,
-        16 * 1024 * 1024,
-        32 * 1024 * 1024,
-        64 * 1024 * 1024,
-    ];
-
-    let test_data_size = BENCHMARK_DATA_SIZE.min(source_size as usize);
-    let mut best_size = DEFAULT_BUFFER_SIZE;
-    let mut best_speed = 0.0;
+    let mut download = if from_url {
+        match Download::open(source_path) {
+            Ok(download) => Some(download),
+            Err(e) => {
+                eprintln!("Error: Cannot download '{}': {}", source_path, e);
+                Exit::Source.exit();
+            }
+        }
+    } else {
+        None
+    };
+    if let (Some(download), Some((algo, _))) = (download.as_mut(), &expected_checksum) {
+        download.hash_with(*algo);
+    }
 
-    println!(
-        "  Testing buffer sizes with {}MB of data...",
-        test_data_size / 1_048_576
-    );
+    // Open the source file, or every part of it, before anything is written
+    let mut source_file = if from_stdin || from_url {
+        None
+    } else {
+        let mut files = Vec::with_capacity(source_paths.len());
+        for path in source_paths {
+            match File::open(path) {
+                Ok(file) => files.push(file),
+                Err(e) => {
+                    eprintln!("Error: Cannot open source file '{}': {}", path, e);
+                    Exit::Source.exit();
+                }
+            }
+        }
+        match SourceFile::new(files) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Error: Cannot read source file metadata: {}", e);
+                Exit::Source.exit();
+            }
+        }
+    };
 
-    for &buffer_size in &test_sizes {
-        source.seek(SeekFrom::Start(0))?;
+    let source_size = match &source_file {
+        None => download.as_ref().and_then(|download| download.size),
+        Some(file) => Some(file.size()),
+    };
 
-        let start = Instant::now();
-        // Use aligned buffer for O_DIRECT compatibility
-        let mut buffer = vec![0u8; buffer_size];
-        let mut written = 0;
+    let compression = match source_file.as_mut() {
+        Some(file) if decompress => match detect_compression(file) {
+            Ok(compression) => compression,
+            Err(e) => {
+                eprintln!("Error: Cannot read source file '{}': {}", source_path, e);
+                Exit::Source.exit();
+            }
+        },
+        None if decompress => match download.as_mut().map(Download::peek) {
+            Some(Ok(magic)) => Compression::detect(magic),
+            Some(Err(e)) => {
+                eprintln!("Error: Cannot download '{}': {}", source_path, e);
+                Exit::Source.exit();
+            }
+            None => None,
+        },
+        _ => None,
+    };
 
-        // For the benchmark, we'll just read from source without writing to target
-        // to avoid O_DIRECT alignment issues during benchmarking
-        while written < test_data_size {
-            let to_read = buffer_size.min(test_data_size - written);
-            let bytes_read = source.read(&mut buffer[..to_read])?;
-            if bytes_read == 0 {
-                break;
+    // --skip counts bytes of the image as written, so a compressed file is decompressed as
+    // far as it to see whether it gets there. Compressed downloads and stdin can only be
+    // read once; their copy fails on reaching the end before --skip.
+    if let Some(size) = source_size.filter(|&size| compression.is_none() && skip > size) {
+        eprintln!(
+            "Error: --skip {} is past the end of the {} byte source",
+            skip, size
+        );
+        Exit::Usage.exit();
+    }
+    if let Some(file) = source_file
+        .as_mut()
+        .filter(|_| compression.is_some() && skip > 0)
+    {
+        let reached = file.seek(SeekFrom::Start(0)).and_then(|_| {
+            let reader = open_source_reader(&mut *file, compression)?;
+            io::copy(&mut reader.take(skip), &mut io::sink())
+        });
+        match reached {
+            Ok(size) if size < skip => {
+                eprintln!(
+                    "Error: --skip {} is past the end of the {} byte decompressed image",
+                    skip, size
+                );
+                Exit::Usage.exit();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error: Cannot read source file '{}': {}", source_path, e);
+                Exit::Source.exit();
             }
-            written += bytes_read;
         }
+    }
 
-        let elapsed = start.elapsed().as_secs_f64();
-        let speed = written as f64 / elapsed / 1_000_000.0;
+    if compare {
+        let source_file = source_file
+            .as_mut()
+            .expect("--compare is rejected for stdin sources");
+        let mut source = VerifySource {
+            file: source_file,
+            compression,
+            skip,
+            bmap: bmap.as_ref(),
+            partial,
+        };
+        let buffer_size = manual_buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        let compared = compare_targets(
+            &mut source,
+            target_paths,
+            seek,
+            buffer_size,
+            expected_checksum.as_ref(),
+            progress_format,
+        );
+        if let Some(failure) = compared {
+            failure.exit();
+        }
+        return;
+    }
 
-        print!("  {}MB: {:.2} MB/s", buffer_size / 1_048_576, speed);
+    // Anything written raw should start with a partition table or a filesystem; a bare
+    // archive or installer payload won't boot, nor will a plain data ISO with no MBR or El
+    // Torito boot catalog. Writing one may still be what was meant, so these only warn.
+    if let Some(file) = source_file.as_mut() {
+        let is_iso = source_path.to_lowercase().ends_with(".iso");
+        let mut header = vec![0u8; SIGNATURE_PROBE_SIZE];
+        let probed = open_source_at(file, compression, skip)
+            .and_then(|mut reader| read_full(&mut reader, &mut header))
+            .map(|len| (len, ImageSignatures::of(&header[..len])));
+        match probed {
+            Ok((_, found)) if is_iso => {
+                if !found.mbr && !found.el_torito {
+                    warn!(
+                        "⚠️  Warning: '{}' has no MBR boot signature or El Torito boot catalog.",
+                        source_path
+                    );
+                    eprintln!(
+                        "It does not look like a hybrid ISO and will likely not boot from USB."
+                    );
+                }
+            }
+            Ok((len, found)) if len >= 512 && !found.mbr && !found.gpt && !found.iso9660 => {
+                warn!(
+                    "⚠️  Warning: '{}' has no MBR, GPT or ISO 9660 signature.",
+                    source_path
+                );
+                eprintln!("It may not be a disk image meant for raw writing and might not boot.");
+            }
+            Ok(_) => {}
+            Err(e) if is_iso => warn!("⚠️  Warning: Could not inspect ISO boot records: {}", e),
+            Err(_) => {}
+        }
+    }
 
-        if speed > best_speed {
-            best_speed = speed;
-            best_size = buffer_size;
-            println!(" ⭐ (best so far)");
-        } else {
-            println!();
-            // If speed is decreasing, larger buffers won't help
-            if speed < best_speed * 0.95 {
-                break;
+    // Pick up where an earlier --resume run stopped by moving --skip and --seek past what it
+    // wrote. Progress is recorded after each flush, so flush every so often.
+    let mut checkpoint = resume_path.map(|path| {
+        let file = source_file
+            .as_mut()
+            .expect("--resume is rejected for stdin and URL sources");
+        let TargetOffset::Start(seek) = seek else {
+            unreachable!("--resume is rejected for a --seek from the end");
+        };
+        match Checkpoint::new(Path::new(path), file, &target_paths[0], seek, skip) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                eprintln!("Error: Cannot read source file '{}': {}", source_path, e);
+                Exit::Source.exit();
             }
         }
+    });
+    let resumed = match checkpoint.as_mut().map(Checkpoint::load) {
+        None => 0,
+        Some(Ok(resumed)) => resumed,
+        Some(Err(e)) => {
+            eprintln!(
+                "Error: Cannot resume from '{}': {}",
+                resume_path.unwrap_or_default(),
+                e
+            );
+            eprintln!("Remove it to start the write over.");
+            Exit::Usage.exit();
+        }
+    };
+    if resumed > 0 {
+        say!(
+            "⏯️  Resuming after the {:.2} MB an earlier run wrote",
+            resumed as f64 / 1_000_000.0
+        );
     }
+    let seek = match seek {
+        TargetOffset::Start(seek) => TargetOffset::Start(seek + resumed),
+        end => end,
+    };
+    let skip = skip + resumed;
+    let partial = partial.map(|partial| partial.saturating_sub(resumed));
+    let sync_interval = sync_interval.or(checkpoint.as_ref().map(|_| RESUME_SYNC_INTERVAL));
 
-    // Reset source file position after benchmarking
-    source.seek(SeekFrom::Start(0))?;
+    for target_path in target_paths {
+        if source_file.is_some() {
+            refuse_source_on_target(source_paths, target_path);
+        }
+        refuse_file_target(target_path, allow_file);
+        if !partition_ok {
+            warn_if_partition(target_path);
+        }
+        refuse_unsafe_target(target_path, force);
+    }
 
-    Ok(best_size)
-}
+    // Confirm operation
+    let target_list = target_paths.join(", ");
+    say!("Source: {}", source_path);
+    if target_paths.len() == 1 {
+        say!("Target: {}", target_list);
+    } else {
+        say!("Targets: {}", target_list);
+    }
+    let erase_warning = format!(
+        "\n⚠️  WARNING: This will permanently erase all data on {}!",
+        target_list
+    );
 
-fn copy_with_progress(
-    mut source: File,
-    mut target: File,
-    total_size: u64,
-    buffer_size: usize,
-) -> io::Result<()> {
-    let mut buffer = vec![0u8; buffer_size];
+    if assume_yes {
+        say!("{}", erase_warning);
+        say!("Proceeding without confirmation (--yes)");
+    } else {
+        // Shown even with --quiet: it is what the question is about
+        println!("{}", output::display(&erase_warning));
 
-    let mut total_written = 0u64;
-    let start_time = Instant::now();
-    let mut last_update = Instant::now();
+        println!("Are you sure you want to continue? (yes/no): ");
 
-    loop {
-        let bytes_read = source.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+        let mut confirmation = String::new();
+        match console {
+            Some(tty) => BufReader::new(tty).read_line(&mut confirmation),
+            None => io::stdin().read_line(&mut confirmation),
         }
+        .expect("Failed to read input");
 
-        target.write_all(&buffer[..bytes_read])?;
-        total_written += bytes_read as u64;
+        if confirmation.trim().to_lowercase() != "yes" {
+            say!("Operation cancelled.");
+            Exit::Cancelled.exit();
+        }
+    }
 
-        // Update progress every 100ms
-        if last_update.elapsed().as_millis() >= 100 {
-            let elapsed = start_time.elapsed().as_secs_f64();
-            let progress = (total_written as f64 / total_size as f64) * 100.0;
-            let speed = total_written as f64 / elapsed / 1_000_000.0;
-            let eta = if speed > 0.0 {
-                ((total_size - total_written) as f64 / (speed * 1_000_000.0)) as u64
-            } else {
-                0
-            };
+    // Open every target before writing anything, so a typo doesn't leave a half-done batch
+    let mut targets = Vec::with_capacity(target_paths.len());
+    for target_path in target_paths {
+        // The buffer size benchmark reads back what it overwrites in order to restore it
+        let readable =
+            verify || (manual_buffer_size.is_none() && bmap.is_none() && source_file.is_some());
+        // An image file is written through the page cache: O_DIRECT and O_SYNC only slow a
+        // file down, and the final sync flushes it all the same
+        let opened = if allow_file && is_file_target(target_path) {
+            open_file_target(target_path, readable).map(|file| (file, false))
+        } else {
+            System.open_target(target_path, readable)
+        };
+        let (mut file, mut direct_io) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                eprintln!("Error: Cannot open target device '{}': {}", target_path, e);
+                eprintln!("Make sure you have permission (try sudo) and the device exists.");
+                if e.kind() == io::ErrorKind::NotFound && !allow_file {
+                    eprintln!("Use --allow-file to create an image file there instead.");
+                }
+                Exit::Target.exit();
+            }
+        };
+
+        // A disk can only be written in whole logical blocks. Round a --seek that falls inside
+        // one down to where the block starts, unless --strict says to stop instead.
+        let is_block_device = System.is_block_device(&file);
+        let seek = resolve_seek(seek, &file, target_path);
+        let mut offset = seek;
+        if is_block_device {
+            match System.logical_block_size(&file) {
+                Ok(block_size) if !seek.is_multiple_of(block_size) => {
+                    if strict {
+                        eprintln!(
+                            "Error: --seek {} is not a multiple of the {}-byte block size of {}",
+                            seek, block_size, target_path
+                        );
+                        Exit::Usage.exit();
+                    }
+                    offset = seek / block_size * block_size;
+                    warn!(
+                        "⚠️  Warning: --seek {} is not a multiple of the {}-byte block size of {}, writing at {} instead",
+                        seek, block_size, target_path, offset
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "⚠️  Warning: Cannot determine the block size of {}: {}",
+                    target_path, e
+                ),
+            }
+        }
 
-            print!(
-                "\r📝 Progress: {:.1}% | {:.2}/{:.2} MB | Speed: {:.2} MB/s | ETA: {}s   ",
-                progress,
-                total_written as f64 / 1_000_000.0,
-                total_size as f64 / 1_000_000.0,
-                speed,
-                eta
+        // O_DIRECT offsets must also be aligned to the buffer alignment. Rounding further
+        // would write somewhere the user didn't ask for, so write through the page cache.
+        if direct_io && !offset.is_multiple_of(DIRECT_IO_ALIGNMENT as u64) {
+            say!(
+                "ℹ️  --seek {} is not {}-byte aligned, not using direct I/O for {}",
+                offset,
+                DIRECT_IO_ALIGNMENT,
+                target_path
+            );
+            if let Err(e) = System.set_direct_io(&mut file, false) {
+                eprintln!(
+                    "Error: Cannot disable direct I/O on '{}': {}",
+                    target_path, e
+                );
+                Exit::Target.exit();
+            }
+            direct_io = false;
+        }
+
+        // Direct I/O already bypasses the page cache. Otherwise only evict pages for block
+        // devices; a regular file target may well want its cache.
+        if drop_cache && !direct_io && !is_block_device {
+            say!(
+                "ℹ️  --drop-cache has no effect on regular file target {}",
+                target_path
             );
-            io::stdout().flush()?;
-            last_update = Instant::now();
         }
+
+        targets.push(Target {
+            path: target_path.clone(),
+            file,
+            offset,
+            direct_io,
+            drop_cache: drop_cache && !direct_io && is_block_device,
+            sync_interval,
+            block_device: is_block_device,
+            zeroed_until: 0,
+            checkpoint: checkpoint.take(),
+        });
     }
 
-    // Final sync to ensure all data is written
-    target.sync_all()?;
+    // Fail now rather than with ENOSPC near the end, unless --force says to try anyway. Only
+    // the raw size is known up front; decompressed and stdin sources are checked by the
+    // write itself.
+    let image_size = match &bmap {
+        Some(map) => Some(map.image_size),
+        None => source_size
+            .filter(|_| compression.is_none())
+            .map(|size| size - skip)
+            .map(|size| partial.map_or(size, |partial| size.min(partial))),
+    };
+    if let Some(size) = image_size {
+        for target in &targets {
+            let size = size + target.offset;
+            let capacity = if target.block_device {
+                System.device_size(&target.file)
+            } else {
+                System.file_capacity(&target.file, Path::new(&target.path))
+            };
+            match capacity {
+                Ok(capacity) if capacity < size => {
+                    let message = format!(
+                        "Source needs {:.2} MB but {} is only {:.2} MB",
+                        size as f64 / 1_000_000.0,
+                        target.path,
+                        capacity as f64 / 1_000_000.0
+                    );
+                    if !force {
+                        eprintln!("Error: {}", message);
+                        eprintln!("Use --force to write as much as fits anyway.");
+                        Exit::Target.exit();
+                    }
+                    warn!("⚠️  Warning: {}", message);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "⚠️  Warning: Cannot determine the capacity of {}: {}",
+                        target.path, e
+                    );
+                }
+            }
+        }
+    }
 
-    let elapsed = start_time.elapsed().as_secs_f64();
-    let avg_speed = total_written as f64 / elapsed / 1_000_000.0;
+    // An image file ends up the size of the image: cut down when written from the start, so
+    // nothing of an older and longer image is left at its end, and grown when the image
+    // reaches past its end. With the size unknown it grows as the write goes.
+    for target in &targets {
+        let Ok(metadata) = target.file.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let end = image_size.map(|size| target.offset + size);
+        let new_len = match end {
+            _ if target.offset == 0 => end.unwrap_or(0),
+            Some(end) if end > metadata.len() => end,
+            _ => continue,
+        };
+        if new_len == metadata.len() {
+            continue;
+        }
+        if let Err(e) = target.file.set_len(new_len) {
+            eprintln!(
+                "Error: Cannot resize '{}' to {} bytes: {}",
+                target.path, new_len, e
+            );
+            Exit::Target.exit();
+        }
+        if end.is_some() {
+            say!("📄 Sized image file {} to {} bytes", target.path, new_len);
+        }
+    }
 
-    print!(
-        "\r📝 Progress: 100.0% | {:.2}/{:.2} MB | Avg Speed: {:.2} MB/s | Time: {:.1}s   ",
-        total_written as f64 / 1_000_000.0,
-        total_size as f64 / 1_000_000.0,
-        avg_speed,
-        elapsed
-    );
-    io::stdout().flush()?;
+    match source_size {
+        Some(size) => say!(
+            "\n📦 Source size: {:.2} MB ({} bytes)",
+            size as f64 / 1_000_000.0,
+            size
+        ),
+        None if from_url => say!("\n📦 Source: {} (size unknown)", source_path),
+        None => say!("\n📦 Source: stdin (size unknown)"),
+    }
+    if let Some(map) = &bmap {
+        say!(
+            "🗺️  Block map: {:.2} MB of the {:.2} MB image is mapped",
+            map.mapped_size() as f64 / 1_000_000.0,
+            map.image_size as f64 / 1_000_000.0
+        );
+        if map.checksum_algo.is_none() {
+            say!("ℹ️  The block map has no SHA256/MD5 digests; ranges will not be checked");
+        }
+    }
+    if skip > 0 {
+        say!("⏭️  Skipping the first {} bytes of the source", skip);
+    }
+    match seek {
+        TargetOffset::Start(0) => {}
+        TargetOffset::Start(seek) => say!("⏩ Writing at offset {} on the target", seek),
+        TargetOffset::End(back) => {
+            for target in &targets {
+                say!(
+                    "⏩ Writing at offset {} on {}, {} bytes before its end",
+                    target.offset,
+                    target.path,
+                    back
+                );
+            }
+        }
+    }
+    if let Some(partial) = partial {
+        say!("✂️  Writing only the first {} bytes of the image", partial);
+    }
+    for target in &targets {
+        if target.direct_io {
+            say!("⚡ Direct I/O (O_DIRECT) enabled for {}", target.path);
+        }
+    }
+    if let Some(rate) = max_rate {
+        say!("🐢 Write speed capped at {:.2} MB/s", rate / 1_000_000.0);
+    }
+    if let Some(timeout) = timeout {
+        say!(
+            "⏱️  Giving up if the write makes no progress for {}",
+            format_duration(timeout)
+        );
+    }
+    if let Some(compression) = compression {
+        say!(
+            "🗜️  Source is {}-compressed and will be decompressed on the fly",
+            compression.name()
+        );
+    }
 
-    Ok(())
+    // Determine buffer size
+    let (buffer_size, buffer_size_from) = choose_buffer_size(buffer_size_setting, || {
+        if bmap.is_some() {
+            // The benchmark writes the raw start of the source, which would land in unmapped
+            // blocks
+            say!("ℹ️  Writing from a block map, skipping auto-detection and using default 16MB\n");
+            return None;
+        }
+        let Some(file) = source_file.as_mut() else {
+            say!("ℹ️  Source is not seekable, skipping auto-detection and using default 16MB\n");
+            return None;
+        };
+        say!("🔍 Auto-detecting optimal buffer size...");
+        // Every target is written with the same buffer size, so only the first is measured
+        // and stands in for the batch
+        if targets.len() > 1 {
+            say!(
+                "ℹ️  Measuring {} only; its buffer size is used for all {} targets",
+                targets[0].path,
+                targets.len()
+            );
+        }
+        match detect_optimal_buffer_size(file, compression, &mut targets[0], skip) {
+            Ok(size) => Some(size),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                warn!(
+                    "\n❌ Interrupted while measuring the buffer size; {} was put back as it was",
+                    targets[0].path
+                );
+                Exit::Interrupted.exit();
+            }
+            Err(e) => {
+                warn!(
+                    "⚠️  Warning: Auto-detection failed ({}), using default 16MB\n",
+                    e
+                );
+                None
+            }
+        }
+    });
+    let buffer_size_mb = buffer_size as f64 / 1_048_576.0;
+    match buffer_size_from {
+        BufferSizeFrom::Flag => say!(
+            "🔧 Using manually specified buffer size: {:.1}MB\n",
+            buffer_size_mb
+        ),
+        BufferSizeFrom::Env => say!(
+            "🔧 Using buffer size from {}: {:.1}MB\n",
+            BUFFER_SIZE_VAR,
+            buffer_size_mb
+        ),
+        BufferSizeFrom::Detected => {
+            say!("✅ Optimal buffer size detected: {:.1}MB\n", buffer_size_mb)
+        }
+        BufferSizeFrom::Default => {}
+    }
+
+    // Reset file positions after benchmark
+    if manual_buffer_size.is_none() {
+        if let Some(file) = source_file.as_mut() {
+            file.seek(SeekFrom::Start(0)).ok();
+        }
+    }
+    for target in &mut targets {
+        if let Err(e) = target.file.seek(SeekFrom::Start(target.offset)) {
+            eprintln!(
+                "Error: Cannot seek {} to offset {}: {}",
+                target.path, target.offset, e
+            );
+            Exit::Target.exit();
+        }
+    }
+
+    // Catch a truncated or corrupt download before spending time writing it
+    if let (Some((algo, expected)), Some(file), Some(size)) =
+        (&expected_checksum, source_file.as_mut(), source_size)
+    {
+        say!("🔐 Checking source {}...", algo.name());
+        match verify_checksum(file, size, buffer_size, *algo, expected, progress_format) {
+            Ok(true) => say!("✅ Source {} matches\n", algo.name()),
+            Ok(false) => Exit::Source.exit(),
+            Err(e) => {
+                warn!("\n❌ Error while computing checksum: {}", e);
+                Exit::Source.exit();
+            }
+        }
+    }
+
+    if let (Some((algo, _)), Some(_)) = (&expected_checksum, &download) {
+        say!(
+            "🔐 The download's {} is checked as it streams\n",
+            algo.name()
+        );
+    }
+
+    // An unwritable state file should stop the run now, not go unnoticed until it's needed
+    if let Some(checkpoint) = targets.iter().find_map(|target| target.checkpoint.as_ref()) {
+        if let Err(e) = checkpoint.record(0) {
+            eprintln!(
+                "Error: Cannot write '{}': {}",
+                resume_path.unwrap_or_default(),
+                e
+            );
+            Exit::Usage.exit();
+        }
+    }
+
+    if sparse {
+        for target in &mut targets {
+            target.zeroed_until = prepare_sparse(target, image_size);
+        }
+    }
+
+    say!("🚀 Starting write operation...\n");
+
+    // Perform the copy operation; neither stdin nor decompressed sizes are known up front
+    let total_size = match &bmap {
+        Some(map) => Some(map.mapped_size()),
+        None => image_size,
+    };
+
+    // Recorded before the final short chunk can turn direct I/O off
+    let direct_io: Vec<bool> = targets.iter().map(|target| target.direct_io).collect();
+    let started = SystemTime::now();
+    let write_start = Instant::now();
+    if parsed.flag("notify") {
+        notify::arm(source_path, &target_list);
+    }
+
+    System.set_interrupt_handler(true);
+    let watchdog = timeout.map(watch_progress);
+    let reader = match (source_file.as_mut(), download.as_mut(), &bmap) {
+        // Seek over holes in a plain image rather than reading them
+        (Some(file), _, Some(map)) if compression.is_none() => file
+            .seek(SeekFrom::Start(0))
+            .map(|_| Box::new(MappedReader::new(file, map, true)) as Box<dyn Read>),
+        (Some(file), _, map) => open_source_at(file, compression, skip).map(|reader| match map {
+            Some(map) => Box::new(MappedReader::new(reader, map, true)) as Box<dyn Read>,
+            None => reader,
+        }),
+        (None, Some(download), map) => {
+            open_source_reader(download, compression).and_then(|mut reader| {
+                skip_source(&mut reader, skip)?;
+                Ok(match map {
+                    Some(map) => Box::new(MappedReader::new(reader, map, true)) as Box<dyn Read>,
+                    None => reader,
+                })
+            })
+        }
+        (None, None, map) => {
+            let mut stdin = io::stdin().lock();
+            skip_source(&mut stdin, skip).map(|_| {
+                let stdin = Box::new(stdin) as Box<dyn Read>;
+                match map {
+                    Some(map) => Box::new(MappedReader::new(stdin, map, true)) as Box<dyn Read>,
+                    None => stdin,
+                }
+            })
+        }
+    };
+    // --partial stops the copy once that much of the image has been read
+    let reader = reader.map(|reader| match partial {
+        Some(partial) => Box::new(reader.take(partial)) as Box<dyn Read>,
+        None => reader,
+    });
+    // --hash-both hashes the image on its way to the targets
+    let mut source_digest = None;
+    let result = reader.and_then(|mut reader| {
+        let writer = Writer::new()
+            .with_buffer_size(buffer_size)
+            .with_max_rate(max_rate)
+            .with_cancel_flag(&INTERRUPTED);
+        let mut copy = |reader: &mut dyn Read| {
+            copy_with_progress(
+                reader,
+                &mut targets,
+                bmap.as_ref(),
+                total_size,
+                &writer,
+                retries,
+                progress_format,
+            )
+        };
+        let Some(algo) = digest_algo else {
+            return copy(&mut reader);
+        };
+        let mut reader = HashingReader::new(reader, algo);
+        let results = copy(&mut reader)?;
+        source_digest = Some(reader.digest());
+        Ok(results)
+    });
+    drop(watchdog);
+    System.set_interrupt_handler(false);
+    let write_duration = write_start.elapsed();
+    let entry = |index: usize, bytes_written: Option<u64>, result, error| Entry {
+        started,
+        source: source_path,
+        target: &target_paths[index],
+        size: total_size,
+        bytes_written,
+        buffer_size,
+        duration: write_duration,
+        direct_io: direct_io[index],
+        verified: None,
+        digest_algo: None,
+        source_digest: None,
+        device_digest: None,
+        result,
+        error,
+    };
+    let results = match result {
+        Ok(results) => results,
+        Err(e) => {
+            let (result, status) = if STALLED.load(Ordering::Relaxed) {
+                warn!("\n❌ Write stalled: {}", e);
+                ("stalled", Exit::Stalled)
+            } else if e.kind() == io::ErrorKind::Interrupted {
+                warn!("\n❌ Write interrupted: {}", e);
+                ("interrupted", Exit::Interrupted)
+            } else {
+                warn!("\n❌ Error during write operation: {}", e);
+                ("failure", Exit::Write)
+            };
+            if let Some(log) = log.as_mut() {
+                let entries: Vec<Entry> = (0..targets.len())
+                    .map(|index| entry(index, None, result, Some(e.to_string())))
+                    .collect();
+                write_log(log, &entries);
+            }
+            status.exit();
+        }
+    };
+    // Why each target failed, if it did, whether writing, wiping or verifying
+    let mut errors: Vec<Option<String>> = results
+        .iter()
+        .map(|result| result.as_ref().err().map(|e| e.to_string()))
+        .collect();
+    let mut verified: Vec<Option<bool>> = vec![None; targets.len()];
+    let mut device_digest: Vec<Option<String>> = vec![None; targets.len()];
+
+    // Report every device; one failure doesn't end the batch. A failed write outranks a
+    // failed verification in the exit status.
+    let mut failure = None;
+    for (target, result) in targets.iter().zip(&results) {
+        match result {
+            Ok(_) => {
+                say!("✅ Successfully written to {}", target.path);
+                // The whole image is there, so there is nothing left to resume
+                if let Some(Err(e)) = target.checkpoint.as_ref().map(Checkpoint::remove) {
+                    warn!("⚠️  Warning: Cannot remove the --resume state file: {}", e);
+                }
+            }
+            Err(e) => {
+                warn!("❌ Failed to write {}: {}", target.path, e);
+                failure = Some(Exit::Write);
+            }
+        }
+    }
+
+    // The digest of a download is only known once it has all been written
+    if let Some(download) = download.as_mut() {
+        let mb = download.received() as f64 / 1_000_000.0;
+        say!(
+            "🌐 Downloaded {:.2} MB in {} ({:.2} MB/s)",
+            mb,
+            format_duration(download.elapsed()),
+            mb / download.elapsed().as_secs_f64().max(0.001)
+        );
+        if let Some((algo, expected)) = &expected_checksum {
+            match download.digest() {
+                Some(computed) if computed == *expected => {
+                    say!("✅ Download {} matches", algo.name())
+                }
+                Some(computed) => {
+                    warn!(
+                        "\n❌ {} mismatch: the targets hold a corrupt image",
+                        algo.name()
+                    );
+                    eprintln!("  Expected: {}", expected);
+                    eprintln!("  Computed: {}", computed);
+                    for error in &mut errors {
+                        error.get_or_insert_with(|| format!("download {} mismatch", algo.name()));
+                    }
+                    failure.get_or_insert(Exit::Source);
+                }
+                None => warn!(
+                    "⚠️  Warning: Only part of the download was read, so its {} was not checked",
+                    algo.name()
+                ),
+            }
+        }
+    }
+
+    if let (Some(algo), Some(digest)) = (digest_algo, &source_digest) {
+        say!("🔐 Image {}:  {}", algo.name(), digest);
+    }
+
+    // Clear out whatever the device held past the end of the new image
+    if wipe_remainder {
+        for (index, (target, result)) in targets.iter_mut().zip(&results).enumerate() {
+            let Ok(written) = result else {
+                continue;
+            };
+            if !target.block_device {
+                say!(
+                    "ℹ️  --wipe-remainder only applies to block devices, skipping {}",
+                    target.path
+                );
+                continue;
+            }
+            say!("\n🧹 Wiping the rest of {}...", target.path);
+            let image_end = bmap.as_ref().map_or(*written, |map| map.image_size);
+            match wipe_after(target, target.offset + image_end, buffer_size) {
+                Ok((_, 0)) => say!("✅ Nothing left to wipe after the image"),
+                Ok((Wipe::Discarded, bytes)) => say!(
+                    "✅ Discarded {:.2} MB after the image",
+                    bytes as f64 / 1_000_000.0
+                ),
+                Ok((Wipe::Zeroed, bytes)) => say!(
+                    "✅ Zeroed {:.2} MB after the image (discard not supported)",
+                    bytes as f64 / 1_000_000.0
+                ),
+                Err(e) => {
+                    warn!("❌ Error wiping the rest of {}: {}", target.path, e);
+                    errors[index] = Some(format!("wiping the remainder: {}", e));
+                    failure = Some(Exit::Write);
+                }
+            }
+        }
+    }
+
+    if verify {
+        let source_file = source_file
+            .as_mut()
+            .expect("--verify is rejected for stdin sources");
+        for (index, (target, result)) in targets.iter_mut().zip(&results).enumerate() {
+            let Ok(written) = result else {
+                continue;
+            };
+            let mut source = VerifySource {
+                file: &mut *source_file,
+                compression,
+                skip,
+                bmap: bmap.as_ref(),
+                partial,
+            };
+            let (mut passed, digest) = verify_target(
+                &mut source,
+                target,
+                *written,
+                buffer_size,
+                expected_checksum.as_ref(),
+                digest_algo,
+                progress_format,
+            );
+            if let (Some(digest), Some(image_digest)) = (&digest, &source_digest) {
+                say!(
+                    "🔐 Device {}: {}",
+                    digest_algo.map_or("", ChecksumAlgo::name),
+                    digest
+                );
+                if digest == image_digest {
+                    say!("✅ The digests of the image and {} match", target.path);
+                } else {
+                    warn!("❌ The digests of the image and {} differ", target.path);
+                    passed = false;
+                }
+            }
+            verified[index] = Some(passed);
+            device_digest[index] = digest;
+            if !passed {
+                errors[index].get_or_insert_with(|| "verification failed".to_string());
+                failure.get_or_insert(Exit::Mismatch);
+            }
+        }
+    }
+
+    if let Some(log) = log.as_mut() {
+        let entries: Vec<Entry> = results
+            .iter()
+            .enumerate()
+            .map(|(index, result)| {
+                let error = errors[index].take();
+                let outcome = if error.is_some() {
+                    "failure"
+                } else {
+                    "success"
+                };
+                Entry {
+                    verified: verified[index],
+                    digest_algo: digest_algo.map(ChecksumAlgo::name),
+                    source_digest: source_digest.as_deref(),
+                    device_digest: device_digest[index].as_deref(),
+                    ..entry(index, result.as_ref().ok().copied(), outcome, error)
+                }
+            })
+            .collect();
+        write_log(log, &entries);
+    }
+
+    if let Some(failure) = failure {
+        failure.exit();
+    }
+    notify::send(None);
+}
+
+// `mkdev /dev/sdc /dev/sdc`, or the image kept on the stick it is written to, would have the
+// copy reading back what it has just overwritten. No --force for this one.
+fn refuse_source_on_target(source_paths: &[String], target_path: &str) {
+    for source_path in source_paths {
+        if System.overwrites(Path::new(target_path), Path::new(source_path)) {
+            eprintln!(
+                "Error: Writing to '{}' would overwrite the source '{}'",
+                target_path, source_path
+            );
+            eprintln!("They are the same file or device, or the source is stored on the target.");
+            Exit::Target.exit();
+        }
+    }
+}
+
+// A mistyped target that happens to name an existing file would be overwritten without a
+// word, so files need --allow-file. Paths that can't be looked at are left for opening the
+// target to report, as are device paths Windows can't stat.
+fn refuse_file_target(target_path: &str, allow_file: bool) {
+    let Ok(metadata) = fs::metadata(target_path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        eprintln!("Error: {} is a directory, not a device", target_path);
+        Exit::Target.exit();
+    }
+    if metadata.is_file() && !allow_file {
+        eprintln!(
+            "Error: {} is a regular file, not a block device",
+            target_path
+        );
+        eprintln!("Use --allow-file to write the image into this file on purpose.");
+        Exit::Target.exit();
+    }
+}
+
+// With --allow-file, a regular file or a path where nothing is yet, which is then created
+fn is_file_target(target_path: &str) -> bool {
+    match fs::metadata(target_path) {
+        Ok(metadata) => metadata.is_file(),
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+    }
+}
+
+// Opened as it is: sizing it to the image waits until the image size is known
+fn open_file_target(target_path: &str, readable: bool) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .read(readable)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(target_path)
+}
+
+// Refuse to clobber a disk that is in use or looks like an internal/system disk, and stop
+// before the prompt for one that can't be written at all
+fn refuse_unsafe_target(target_path: &str, force: bool) {
+    if let Ok(true) = System.is_write_protected(Path::new(target_path)) {
+        eprintln!("Error: {} is read-only / write-protected", target_path);
+        eprintln!("If the card or its adapter has a lock switch, slide it away from 'Lock' and");
+        eprintln!("reinsert it.");
+        Exit::Target.exit();
+    }
+    let Some(device) = System.block_device(Path::new(target_path)) else {
+        return;
+    };
+    if !System::CHECKS_MOUNTS {
+        warn!(
+            "⚠️  Warning: Cannot tell on this system whether {} is mounted or holds the system;",
+            target_path
+        );
+        eprintln!("make sure it is the disk you mean to erase.");
+        return;
+    }
+    let mut dangerous = false;
+
+    for (source, mountpoint) in System.mounted_filesystems(&device) {
+        if System::DISMOUNTS_ON_OPEN {
+            say!("ℹ️  {} ({}) will be dismounted", mountpoint, source);
+            continue;
+        }
+        warn!("⚠️  Warning: {} is mounted at {}", source, mountpoint);
+        dangerous = true;
+    }
+
+    if !System.is_removable(&device) {
+        warn!(
+            "⚠️  Warning: {} is not a removable disk and may hold your system",
+            System.disk_path(&device)
+        );
+        dangerous = true;
+    }
+
+    if dangerous && !force {
+        eprintln!(
+            "Refusing to write to {}. Use --force to override.",
+            target_path
+        );
+        Exit::Target.exit();
+    }
+}
+
+// A bootable image written to /dev/sdc1 instead of /dev/sdc doesn't boot, and the two are
+// easy to mix up
+fn warn_if_partition(target_path: &str) {
+    let Some(device) = System.block_device(Path::new(target_path)) else {
+        return;
+    };
+    if System.is_partition(&device) {
+        warn!(
+            "⚠️  Warning: {} is a partition, not a whole disk.",
+            target_path
+        );
+        eprintln!(
+            "A bootable image usually goes to {}; use --partition if the partition is intended.",
+            System.disk_path(&device)
+        );
+    }
+}
+
+// Progress for one of `rows` targets: redrawn in place on a terminal, plain when asked for,
+// and a line every few seconds when stdout goes to a file or pipe
+fn human_reporter(label: &str, row: usize, rows: usize) -> Box<dyn ProgressReporter> {
+    let label = output::display(label);
+    if !io::stdout().is_terminal() {
+        return Box::new(LineReporter::new(&label));
+    }
+    let reporter = TtyReporter::for_row(&label, row, rows);
+    if output::plain() {
+        Box::new(reporter.without_escapes())
+    } else {
+        Box::new(reporter)
+    }
+}
+
+// A log that can't be written doesn't undo the write, so this only warns
+fn write_log(log: &mut OperationLog, entries: &[Entry]) {
+    for entry in entries {
+        if let Err(e) = log.record(entry) {
+            warn!("⚠️  Warning: Cannot write to the log: {}", e);
+            return;
+        }
+    }
+}
+
+// Read `target` back against the source and, when a digest is expected, check its hash too.
+// Prints the outcome and returns whether the device passed, with the digest of what was read
+// back if `hash_device` asks for it and the device matched.
+fn verify_target(
+    source: &mut VerifySource,
+    target: &mut Target,
+    written: u64,
+    buffer_size: usize,
+    expected_checksum: Option<&(ChecksumAlgo, String)>,
+    hash_device: Option<ChecksumAlgo>,
+    progress_format: ProgressFormat,
+) -> (bool, Option<String>) {
+    say!("\n🔎 Verifying {}...\n", target.path);
+    // Read back through the regular path; the verify buffers are not block-aligned
+    if target.direct_io {
+        if let Err(e) = System.set_direct_io(&mut target.file, false) {
+            warn!(
+                "\n❌ Error preparing verification of {}: {}",
+                target.path, e
+            );
+            return (false, None);
+        }
+        target.direct_io = false;
+    }
+    // Otherwise the pages just written would be read back from memory, and a device that
+    // lost them would still pass
+    if let Err(e) = System.drop_cache(&target.file) {
+        warn!(
+            "⚠️  Couldn't drop the cached data of {}, so it may be verified from memory: {}",
+            target.path, e
+        );
+    }
+    // A published digest of a compressed download, or of the whole file when --skip,
+    // --partial or a block map left part of it out, says nothing about the bytes on the device
+    let mut device_hasher = expected_checksum
+        .filter(|_| {
+            source.compression.is_none()
+                && source.skip == 0
+                && source.bmap.is_none()
+                && source.partial.is_none()
+        })
+        .map(|(algo, _)| algo.hasher());
+    let mut device_digest = None;
+    let writer = Writer::new().with_buffer_size(buffer_size);
+    let mut reporter: Box<dyn ProgressReporter> = match progress_format {
+        ProgressFormat::Human => human_reporter("🔎 Verifying", 0, 1),
+        ProgressFormat::Json => Box::new(
+            JsonReporter::new()
+                .with_target(&target.path)
+                .with_phase("verify"),
+        ),
+        ProgressFormat::Quiet => Box::new(NullReporter),
+    };
+    let result = target
+        .file
+        .seek(SeekFrom::Start(target.offset))
+        .and_then(|_| open_source_at(source.file, source.compression, source.skip))
+        .and_then(|mut reader| match (source.bmap, hash_device) {
+            // Compare only the mapped ranges, checking the device against the map's digests
+            (Some(map), _) => writer.verify(
+                &mut MappedReader::new(reader, map, false),
+                &mut MappedReader::new(&mut target.file, map, true),
+                written,
+                device_hasher.as_mut(),
+                &mut *reporter,
+            ),
+            (None, Some(algo)) => {
+                let mut device = HashingReader::new(&mut target.file, algo);
+                let result = writer.verify(
+                    &mut reader,
+                    &mut device,
+                    written,
+                    device_hasher.as_mut(),
+                    &mut *reporter,
+                );
+                device_digest = Some(device.digest());
+                result
+            }
+            (None, None) => writer.verify(
+                &mut reader,
+                &mut target.file,
+                written,
+                device_hasher.as_mut(),
+                &mut *reporter,
+            ),
+        });
+    match result {
+        Ok(None) => say!("✅ Verification passed: {} matches the source", target.path),
+        Ok(Some(offset)) => {
+            warn!(
+                "❌ Verification failed for {}: first difference at byte offset {}",
+                target.path,
+                target.offset + source.bmap.map_or(offset, |map| map.image_offset(offset))
+            );
+            return (false, None);
+        }
+        Err(e) => {
+            warn!("\n❌ Error during verification of {}: {}", target.path, e);
+            return (false, None);
+        }
+    }
+
+    if let (Some(hasher), Some((algo, expected))) = (device_hasher, expected_checksum) {
+        let computed = to_hex(&hasher.finalize());
+        if !report_checksum(*algo, &computed, expected) {
+            return (false, None);
+        }
+        say!("✅ Device {} matches", algo.name());
+    }
+
+    (true, device_digest)
+}
+
+// --compare: check devices written earlier against the source, opening them read-only and
+// going through the same comparison as --verify. Returns how it failed, if any target
+// could not be opened or doesn't match.
+fn compare_targets(
+    source: &mut VerifySource,
+    target_paths: &[String],
+    seek: TargetOffset,
+    buffer_size: usize,
+    expected_checksum: Option<&(ChecksumAlgo, String)>,
+    progress_format: ProgressFormat,
+) -> Option<Exit> {
+    // How many bytes the write would have put on the device
+    let image_size = match source.bmap {
+        Some(map) => Ok(map.mapped_size()),
+        None => open_source_at(source.file, source.compression, source.skip)
+            .and_then(|mut reader| io::copy(&mut reader, &mut io::sink())),
+    };
+    let image_size = match image_size {
+        Ok(size) => source.partial.map_or(size, |partial| size.min(partial)),
+        Err(e) => {
+            eprintln!("Error: Cannot read the source: {}", e);
+            Exit::Source.exit();
+        }
+    };
+
+    let mut failure = None;
+    for target_path in target_paths {
+        let file = match File::open(target_path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("❌ Cannot open '{}' for reading: {}", target_path, e);
+                failure = Some(Exit::Target);
+                continue;
+            }
+        };
+        let offset = resolve_seek(seek, &file, target_path);
+        let mut target = Target {
+            path: target_path.clone(),
+            block_device: System.is_block_device(&file),
+            file,
+            offset,
+            direct_io: false,
+            drop_cache: false,
+            sync_interval: None,
+            zeroed_until: 0,
+            checkpoint: None,
+        };
+        let (matched, _) = verify_target(
+            source,
+            &mut target,
+            image_size,
+            buffer_size,
+            expected_checksum,
+            None,
+            progress_format,
+        );
+        if !matched {
+            failure.get_or_insert(Exit::Mismatch);
+        }
+    }
+    failure
+}
+
+// The offset a --seek comes to on an opened target. One from the end needs the target's
+// size, and must not reach back past its start.
+fn resolve_seek(seek: TargetOffset, file: &File, target_path: &str) -> u64 {
+    let back = match seek {
+        TargetOffset::Start(offset) => return offset,
+        TargetOffset::End(back) => back,
+    };
+    let size = if System.is_block_device(file) {
+        System.device_size(file)
+    } else {
+        file.metadata().map(|metadata| metadata.len())
+    };
+    match size {
+        Ok(size) if back <= size => size - back,
+        Ok(size) => {
+            eprintln!(
+                "Error: --seek -{} reaches back past the start of {}, which is {} bytes",
+                back, target_path, size
+            );
+            Exit::Usage.exit();
+        }
+        Err(e) => {
+            eprintln!(
+                "Error: Cannot determine the size of {} to seek from its end: {}",
+                target_path, e
+            );
+            Exit::Target.exit();
+        }
+    }
+}
+
+// --generate-completions: files for the source, whole disks for the targets
+fn print_completions(shell: &str) {
+    let Some(shell) = Shell::from_name(shell) else {
+        eprintln!("Error: Unknown shell '{}'. Use bash, zsh or fish", shell);
+        Exit::Usage.exit();
+    };
+    let command = Command {
+        name: "mkdev",
+        options: OPTIONS,
+        values: vec![
+            (
+                "generate-completions",
+                Complete::Words(vec!["bash".into(), "zsh".into(), "fish".into()]),
+            ),
+            ("bmap", Complete::Files),
+            ("log", Complete::Files),
+            (
+                "checksum-algo",
+                Complete::Words(vec!["sha256".into(), "md5".into(), "crc32".into()]),
+            ),
+            (
+                "progress",
+                Complete::Words(vec!["human".into(), "json".into()]),
+            ),
+        ],
+        positionals: vec![Complete::Files, Complete::Command("lsblk -dpno NAME")],
+    };
+    print!("{}", completions::generate(shell, &command));
+}
+
+// Print a table of physical disks that could be written to
+fn list_devices() {
+    let disks = match System.disks() {
+        Ok(disks) => disks,
+        Err(e) => {
+            eprintln!("Error: Cannot list disks: {}", e);
+            Exit::Target.exit();
+        }
+    };
+
+    if disks.is_empty() {
+        println!("No disks found.");
+        return;
+    }
+
+    println!("{:<16} {:>10}  {:<9}  MODEL", "DEVICE", "SIZE", "REMOVABLE");
+    for disk in &disks {
+        let removable = if disk.removable { "yes" } else { "no" };
+        let marker = match &disk.hosts {
+            Some(root) => format!("  ⚠️  system disk (hosts {})", root),
+            None => String::new(),
+        };
+
+        let line = format!(
+            "{:<16} {:>10}  {:<9}  {:<24}{}",
+            disk.path,
+            format_capacity(disk.size),
+            removable,
+            disk.model,
+            marker
+        );
+        println!("{}", output::display(line.trim_end()));
+    }
+}
+
+fn format_capacity(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// What the start of an image says about how it boots: an MBR boot signature, a GPT header
+// in LBA 1, an ISO 9660 primary volume descriptor at sector 16 and an El Torito boot record
+// at sector 17, each false when the header is too short to hold it
+struct ImageSignatures {
+    mbr: bool,
+    gpt: bool,
+    iso9660: bool,
+    el_torito: bool,
+}
+
+impl ImageSignatures {
+    fn of(header: &[u8]) -> ImageSignatures {
+        let descriptor = |sector: u64| header.get((sector * ISO_SECTOR_SIZE) as usize..);
+        let el_torito = descriptor(EL_TORITO_SECTOR).is_some_and(|record| {
+            record.first() == Some(&0)
+                && record.get(1..6) == Some(b"CD001")
+                && record
+                    .get(7..)
+                    .is_some_and(|id| id.starts_with(b"EL TORITO SPECIFICATION"))
+        });
+        ImageSignatures {
+            mbr: header.len() >= 512 && header[510] == 0x55 && header[511] == 0xAA,
+            gpt: header.get(512..520) == Some(b"EFI PART"),
+            iso9660: descriptor(16).and_then(|pvd| pvd.get(1..6)) == Some(b"CD001"),
+            el_torito,
+        }
+    }
+}
+
+// Time writing the start of the image to the target with each candidate buffer size.
+// The sample is what the copy will write: decompressed, and from the --skip offset on.
+// The benchmark only writes where the image itself is going, and what was there is read
+// first and put back afterwards, so a run that stops before the copy (a bad checksum, an
+// unreadable source) leaves the target as it found it.
+fn detect_optimal_buffer_size(
+    source: &mut SourceFile,
+    compression: Option<Compression>,
+    target: &mut Target,
+    source_offset: u64,
+) -> io::Result<usize> {
+    let mut sample = AlignedBuffer::new(BENCHMARK_DATA_SIZE);
+    let sampled = {
+        let mut reader = open_source_at(source, compression, source_offset)?;
+        read_full(&mut reader, &mut sample[..BENCHMARK_DATA_SIZE])?
+    };
+    // Keep the sample block-aligned so it can be written under O_DIRECT
+    let test_data_size = sampled / DIRECT_IO_ALIGNMENT * DIRECT_IO_ALIGNMENT;
+    if test_data_size == 0 {
+        return Err(io::Error::other("source is too small to benchmark"));
+    }
+
+    // A regular file target may be shorter than the sample, or empty
+    let original_len = target.file.metadata()?.len();
+    let mut original = AlignedBuffer::new(test_data_size);
+    target.file.seek(SeekFrom::Start(target.offset))?;
+    let stashed = read_full(&mut target.file, &mut original[..test_data_size])?;
+
+    say!(
+        "  Testing write speed with {}MB of data...",
+        test_data_size / 1_048_576
+    );
+    // Ctrl-C from here on only stops the timing, so that what was there is put back before
+    // mkdev exits
+    System.set_interrupt_handler(true);
+    let candidates = buffer_size_candidates(&target.file, target.block_device);
+    let best_size = fastest_buffer_size(
+        &mut target.file,
+        &sample[..test_data_size],
+        target.offset,
+        &candidates,
+    );
+    let restored = restore_region(target, &original[..stashed], original_len);
+    System.set_interrupt_handler(false);
+    restored?;
+
+    // Reset file positions after benchmarking
+    source.seek(SeekFrom::Start(source_offset))?;
+    target.file.seek(SeekFrom::Start(target.offset))?;
+
+    best_size
+}
+
+fn fastest_buffer_size(
+    target: &mut File,
+    sample: &[u8],
+    offset: u64,
+    candidates: &[usize],
+) -> io::Result<usize> {
+    let mut results: Vec<BenchmarkResult> = Vec::new();
+    let mut best_speed = 0.0;
+
+    for &buffer_size in candidates {
+        let write_speed = time_writes(target, sample, buffer_size, offset)?;
+        results.push(BenchmarkResult {
+            buffer_size,
+            read_speed: None,
+            write_speed,
+        });
+
+        if write_speed > best_speed {
+            best_speed = write_speed;
+        } else if write_speed < best_speed * 0.95 {
+            // If speed is decreasing, larger buffers won't help
+            break;
+        }
+    }
+
+    let best_size = print_benchmark_table(&mut results, false);
+    Ok(best_size.unwrap_or(DEFAULT_BUFFER_SIZE))
+}
+
+// One buffer size's speeds in MB/s; only --benchmark measures reads
+struct BenchmarkResult {
+    buffer_size: usize,
+    read_speed: Option<f64>,
+    write_speed: f64,
+}
+
+// The results as a table, fastest writes first, with the best one marked (in green unless
+// plain). --benchmark prints it even under --quiet, since the table is all it has to say.
+// Returns the best buffer size.
+fn print_benchmark_table(results: &mut [BenchmarkResult], always: bool) -> Option<usize> {
+    results.sort_by(|a, b| b.write_speed.total_cmp(&a.write_speed));
+    let show = |line: String| {
+        if always {
+            println!("{}", output::display(&line));
+        } else {
+            say!("{}", line);
+        }
+    };
+
+    let reads = results.iter().any(|result| result.read_speed.is_some());
+    let speed = |mbps: f64| format!("{:>8.2} MB/s", mbps);
+    let mut header = format!("  {:>8}", "Buffer");
+    if reads {
+        header += &format!("  {:>13}", "Read");
+    }
+    header += &format!("  {:>13}", "Write");
+    show(output::color(&header, "1"));
+
+    for (rank, result) in results.iter().enumerate() {
+        let mut row = format!("  {:>8}", format_buffer_size(result.buffer_size));
+        if let Some(read_speed) = result.read_speed {
+            row += &format!("  {}", speed(read_speed));
+        }
+        row += &format!("  {}", speed(result.write_speed));
+        if rank == 0 {
+            row = format!("{}  ⭐ best", output::color(&row, "1;32"));
+        }
+        show(row);
+    }
+    results.first().map(|result| result.buffer_size)
+}
+
+// The fixed benchmark sizes plus the transfer size the device says suits it best, leaving
+// out any below the minimum it writes efficiently. SD cards and eMMC report their erase
+// block granularity this way; most USB sticks report nothing.
+fn buffer_size_candidates(file: &File, block_device: bool) -> Vec<usize> {
+    let mut sizes = BENCHMARK_BUFFER_SIZES.to_vec();
+    if !block_device {
+        return sizes;
+    }
+    let Ok((minimum, optimal)) = System.io_sizes(file) else {
+        return sizes;
+    };
+
+    let largest = BENCHMARK_BUFFER_SIZES[BENCHMARK_BUFFER_SIZES.len() - 1];
+    let optimal = optimal as usize;
+    if optimal > 0 && optimal <= largest && optimal.is_multiple_of(DIRECT_IO_ALIGNMENT) {
+        sizes.push(optimal);
+    }
+    sizes.retain(|&size| size as u64 >= minimum);
+    sizes.sort_unstable();
+    sizes.dedup();
+    sizes
+}
+
+// "16MB", or "512KB" for the odd sizes a device may report
+fn format_buffer_size(size: usize) -> String {
+    if size.is_multiple_of(1_048_576) {
+        format!("{}MB", size / 1_048_576)
+    } else {
+        format!("{}KB", size / 1024)
+    }
+}
+
+// Put back what the benchmark overwrote at the target offset. Past the end of a regular
+// file nothing was there, so the file is cut back to its old length.
+fn restore_region(target: &mut Target, original: &[u8], original_len: u64) -> io::Result<()> {
+    target.file.seek(SeekFrom::Start(target.offset))?;
+    let aligned = original.len() / DIRECT_IO_ALIGNMENT * DIRECT_IO_ALIGNMENT;
+    target.file.write_all(&original[..aligned])?;
+    if aligned < original.len() {
+        // Only a regular file can end off a block boundary; its tail goes through the cache
+        if target.direct_io {
+            System.set_direct_io(&mut target.file, false)?;
+        }
+        target.file.write_all(&original[aligned..])?;
+        if target.direct_io {
+            System.set_direct_io(&mut target.file, true)?;
+        }
+    }
+    if !target.block_device {
+        target.file.set_len(original_len)?;
+    }
+    target.file.sync_data()
+}
+
+// --benchmark: read the start of the device and write the same bytes back with each
+// candidate buffer size. The device ends up holding exactly what it held before.
+fn benchmark_device(path: &str) -> io::Result<()> {
+    let (mut file, direct_io) = System.open_target(path, true)?;
+    let capacity = if System.is_block_device(&file) {
+        System.device_size(&file)?
+    } else {
+        file.metadata()?.len()
+    };
+
+    let region = (BENCHMARK_DATA_SIZE as u64).min(capacity) as usize / DIRECT_IO_ALIGNMENT
+        * DIRECT_IO_ALIGNMENT;
+    if region == 0 {
+        return Err(io::Error::other("target is too small to benchmark"));
+    }
+
+    say!(
+        "🔍 Benchmarking {} with the first {}MB",
+        path,
+        region / 1_048_576
+    );
+    if !direct_io {
+        say!("ℹ️  Direct I/O is not available; the page cache is dropped before each read");
+    }
+
+    let candidates = buffer_size_candidates(&file, System.is_block_device(&file));
+    let mut data = AlignedBuffer::new(region);
+    let mut results = Vec::with_capacity(candidates.len());
+    for &buffer_size in &candidates {
+        if !direct_io {
+            System.drop_written_pages(&file, 0, region as u64)?;
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let start = Instant::now();
+        for chunk in data[..region].chunks_mut(buffer_size) {
+            file.read_exact(chunk)?;
+        }
+        let read_speed = region as f64 / start.elapsed().as_secs_f64() / 1_000_000.0;
+
+        let write_speed = time_writes(&mut file, &data[..region], buffer_size, 0)?;
+        results.push(BenchmarkResult {
+            buffer_size,
+            read_speed: Some(read_speed),
+            write_speed,
+        });
+    }
+
+    if let Some(buffer_size) = print_benchmark_table(&mut results, true) {
+        say!(
+            "\n✅ Fastest writes with a {} buffer",
+            format_buffer_size(buffer_size)
+        );
+    }
+    Ok(())
+}
+
+// Where the buffer size for a write came from, in order of precedence
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BufferSizeFrom {
+    Flag,
+    Env,
+    Detected,
+    Default,
+}
+
+// --buffer-size wins over SPLASH_MKDEV_BUFFER_SIZE, which counts as unset when empty. None
+// when neither gives a size.
+fn buffer_size_setting(
+    flag: Option<&str>,
+    env: Option<&str>,
+) -> Result<Option<(usize, BufferSizeFrom)>, String> {
+    if let Some(value) = flag {
+        return parse_buffer_size(value).map(|size| Some((size, BufferSizeFrom::Flag)));
+    }
+    match env.filter(|value| !value.is_empty()) {
+        Some(value) => parse_buffer_size(value)
+            .map(|size| Some((size, BufferSizeFrom::Env)))
+            .map_err(|e| format!("{} (from {})", e, BUFFER_SIZE_VAR)),
+        None => Ok(None),
+    }
+}
+
+// A size from the flag or the environment, else the benchmark's, else the 16MB default.
+// `detect` only runs when no size was given, and is None where the benchmark can't run or
+// fails.
+fn choose_buffer_size(
+    setting: Option<(usize, BufferSizeFrom)>,
+    detect: impl FnOnce() -> Option<usize>,
+) -> (usize, BufferSizeFrom) {
+    if let Some(setting) = setting {
+        return setting;
+    }
+    match detect() {
+        Some(size) => (size, BufferSizeFrom::Detected),
+        None => (DEFAULT_BUFFER_SIZE, BufferSizeFrom::Default),
+    }
+}
+
+// A --buffer-size value: a bare number has always meant MB here
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    let parsed = if value.bytes().all(|b| b.is_ascii_digit()) {
+        parse_size(&format!("{}M", value))
+    } else {
+        parse_size(value)
+    };
+    match parsed {
+        Ok(size) if size > 0 && size <= isize::MAX as u64 => Ok(size as usize),
+        Ok(_) => Err(format!("Invalid buffer size '{}'", value)),
+        Err(e) => Err(format!("Invalid buffer size: {}", e)),
+    }
+}
+
+// Write `data` at `offset` in chunks of `buffer_size`, through to the device, and return
+// the speed in MB/s
+fn time_writes(target: &mut File, data: &[u8], buffer_size: usize, offset: u64) -> io::Result<f64> {
+    target.seek(SeekFrom::Start(offset))?;
+    let start = Instant::now();
+    for chunk in data.chunks(buffer_size) {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "interrupted while timing writes",
+            ));
+        }
+        target.write_all(chunk)?;
+    }
+    target.sync_data()?;
+    Ok(data.len() as f64 / start.elapsed().as_secs_f64() / 1_000_000.0)
+}
+
+// Per-target bookkeeping while copying. Behind a mutex so that a target's worker can write
+// to it while the progress callback reads it between chunks.
+struct WriteState<'a> {
+    target: &'a mut Target,
+    written: u64,
+    // Where the next write lands, relative to the target offset. Ahead of `written` once a
+    // block map has skipped holes.
+    position: u64,
+    cache_dropped_until: u64,
+    synced_until: u64,
+    write_time: Duration,
+    error: Option<io::Error>,
+    max_retries: u32,
+    retries: u32,
+    // Zeros left out under --sparse, and where the last of them ended
+    skipped: u64,
+    skipped_until: u64,
+}
+
+impl WriteState<'_> {
+    // A failed target stops receiving data; the others carry on
+    fn write_chunk(&mut self, chunk: &[u8], position: u64) {
+        if self.error.is_some() {
+            return;
+        }
+        let end = position + chunk.len() as u64;
+        if end <= self.target.zeroed_until && chunk.iter().all(|&b| b == 0) {
+            // Already zero on the target; the next write seeks past it
+            self.written += chunk.len() as u64;
+            self.skipped += chunk.len() as u64;
+            self.skipped_until = end;
+            return;
+        }
+        let start = Instant::now();
+        if let Err(e) = self.try_write_chunk(chunk, position) {
+            self.error = Some(e);
+        }
+        self.write_time += start.elapsed();
+    }
+
+    fn try_write_chunk(&mut self, chunk: &[u8], position: u64) -> io::Result<()> {
+        if position != self.position {
+            self.target
+                .file
+                .seek(SeekFrom::Start(self.target.offset + position))?;
+            self.position = position;
+        }
+        // Only the final chunk can be short; finish it without O_DIRECT's length restriction
+        if self.target.direct_io && !chunk.len().is_multiple_of(DIRECT_IO_ALIGNMENT) {
+            System.set_direct_io(&mut self.target.file, false)?;
+            self.target.direct_io = false;
+        }
+        self.write_with_retries(chunk)?;
+        self.written += chunk.len() as u64;
+        self.position += chunk.len() as u64;
+
+        if self.target.drop_cache && self.position - self.cache_dropped_until >= DROP_CACHE_INTERVAL
+        {
+            System.drop_written_pages(
+                &self.target.file,
+                self.target.offset + self.cache_dropped_until,
+                self.target.offset + self.position,
+            )?;
+            self.cache_dropped_until = self.position;
+        }
+
+        // Spread the flush over the write so the progress line tracks what the device holds
+        // and the final sync doesn't stall at 100%
+        if self
+            .target
+            .sync_interval
+            .is_some_and(|interval| self.written - self.synced_until >= interval)
+        {
+            self.target.file.sync_data()?;
+            self.synced_until = self.written;
+            // Only now is it safe for --resume to count on. A state file that can't be
+            // updated just means resuming from further back.
+            if let Some(checkpoint) = &self.target.checkpoint {
+                checkpoint.record(self.written).ok();
+            }
+        }
+        Ok(())
+    }
+
+    // Cheap sticks sometimes fail a single block with EIO and succeed on the next try. A
+    // partial write may have moved the file position, so rewind to the chunk before retrying.
+    fn write_with_retries(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let position = self.target.offset + self.position;
+        let mut attempt = 0;
+        loop {
+            match self.target.file.write_all(chunk) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.max_retries && System.is_transient(&e) => {
+                    attempt += 1;
+                    self.retries += 1;
+                    thread::sleep(RETRY_BACKOFF * attempt);
+                    self.target.file.seek(SeekFrom::Start(position))?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Final sync to ensure all data is written
+    fn finish(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        let start = Instant::now();
+        // A file cut short for --sparse still has to reach the end of the image
+        let mut result = if !self.target.block_device && self.skipped_until > self.position {
+            self.target
+                .file
+                .set_len(self.target.offset + self.skipped_until)
+        } else {
+            Ok(())
+        };
+        result = result.and_then(|_| self.target.file.sync_all());
+        if result.is_ok() && self.target.drop_cache && self.position > self.cache_dropped_until {
+            result = System.drop_written_pages(
+                &self.target.file,
+                self.target.offset + self.cache_dropped_until,
+                self.target.offset + self.position,
+            );
+        }
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+        self.write_time += start.elapsed();
+    }
+}
+
+// The final sync can take a long while on a slow stick once everything has been handed to
+// the kernel, so show that it is still going while `flush` runs. The spinner only appears
+// once the flush has taken a moment, and is cleared again when it's done.
+fn show_flush_progress<F: FnOnce()>(notice: FlushNotice, flush: F) {
+    match notice {
+        FlushNotice::None => return flush(),
+        FlushNotice::Line => {
+            say!("💾 Flushing to device...");
+            return flush();
+        }
+        FlushNotice::Spinner => {}
+    }
+
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let start = Instant::now();
+            let mut drawn = false;
+            while !done.load(Ordering::Relaxed) {
+                thread::sleep(FLUSH_SPINNER_INTERVAL);
+                if start.elapsed() < FLUSH_SPINNER_DELAY || done.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let frame = FLUSH_SPINNER
+                    [(start.elapsed().as_millis() / 100) as usize % FLUSH_SPINNER.len()];
+                let mut stdout = io::stdout().lock();
+                write!(
+                    stdout,
+                    "\r\x1b[K{} Flushing to device... {}",
+                    frame,
+                    format_duration(start.elapsed())
+                )
+                .and_then(|_| stdout.flush())
+                .ok();
+                drawn = true;
+            }
+            if drawn {
+                print!("\r\x1b[K");
+                io::stdout().flush().ok();
+            }
+        });
+        flush();
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+// A poisoned state belonged to a worker that panicked, which ends the copy anyway
+fn lock<'a, 'b>(state: &'a Mutex<WriteState<'b>>) -> MutexGuard<'a, WriteState<'b>> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// What a target's worker is asked to do: write part of the shared chunk, or sync at the end
+enum Job {
+    Write {
+        chunk: Arc<AlignedBuffer>,
+        range: Range<usize>,
+        position: u64,
+    },
+    Finish,
+}
+
+// A thread that writes one target for the whole copy when there are several. It answers
+// each job once done, and stops when its sender is dropped.
+struct TargetWorker {
+    jobs: mpsc::Sender<Job>,
+    done: mpsc::Receiver<()>,
+}
+
+impl TargetWorker {
+    fn spawn<'scope, 'b>(
+        scope: &'scope thread::Scope<'scope, '_>,
+        state: &'scope Mutex<WriteState<'b>>,
+    ) -> TargetWorker
+    where
+        'b: 'scope,
+    {
+        let (jobs, received) = mpsc::channel();
+        let (finished, done) = mpsc::channel();
+        scope.spawn(move || {
+            for job in received {
+                // The chunk is let go before answering, so FanOut can refill it
+                match job {
+                    Job::Write {
+                        chunk,
+                        range,
+                        position,
+                    } => lock(state).write_chunk(&chunk[range], position),
+                    Job::Finish => lock(state).finish(),
+                }
+                if finished.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        TargetWorker { jobs, done }
+    }
+}
+
+// Fans each buffer out to every target, each written by its own worker so they all go at
+// once; a single target is written inline. A failed target drops out and the rest carry on;
+// the write itself only fails once no target is left. With a block map, the data arrives as
+// the mapped ranges back to back and is split up to land at each range's offset.
+struct FanOut<'a, 'b> {
+    states: &'a [Mutex<WriteState<'b>>],
+    // One per target, or none for a single target
+    workers: Vec<TargetWorker>,
+    // The buffer being written, copied here to be shared with the workers
+    chunk: Option<Arc<AlignedBuffer>>,
+    bmap: Option<&'a BlockMap>,
+    range: usize,
+    // Bytes of the current range already written
+    range_written: u64,
+    position: u64,
+    flush_notice: FlushNotice,
+}
+
+impl FanOut<'_, '_> {
+    // Where the next byte goes, and how many bytes fit before the next hole
+    fn next_extent(&mut self) -> (u64, u64) {
+        let Some(map) = self.bmap else {
+            return (self.position, u64::MAX);
+        };
+        while let Some(range) = map.ranges.get(self.range) {
+            if self.range_written < range.len() {
+                return (
+                    range.start + self.range_written,
+                    range.len() - self.range_written,
+                );
+            }
+            self.range += 1;
+            self.range_written = 0;
+        }
+        (map.image_size, u64::MAX)
+    }
+
+    // Copy `buf` into the chunk shared with the workers, which have all let go of it
+    fn share(&mut self, buf: &[u8]) -> Arc<AlignedBuffer> {
+        if self
+            .chunk
+            .as_ref()
+            .is_none_or(|chunk| chunk.len() < buf.len())
+        {
+            self.chunk = Some(Arc::new(AlignedBuffer::new(buf.len())));
+        }
+        let chunk = self.chunk.as_mut().expect("allocated above");
+        Arc::get_mut(chunk).expect("workers drop the chunk before answering")[..buf.len()]
+            .copy_from_slice(buf);
+        Arc::clone(chunk)
+    }
+
+    // Hand every worker a job and wait for all of them to finish it
+    fn run(&self, job: impl Fn() -> Job) -> io::Result<()> {
+        let stopped = || io::Error::other("a target's write thread stopped");
+        for worker in &self.workers {
+            worker.jobs.send(job()).map_err(|_| stopped())?;
+        }
+        for worker in &self.workers {
+            worker.done.recv().map_err(|_| stopped())?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for FanOut<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = if self.workers.is_empty() {
+            None
+        } else {
+            Some(self.share(buf))
+        };
+        let mut rest = 0..buf.len();
+        while !rest.is_empty() {
+            let (position, room) = self.next_extent();
+            let piece = rest.start..rest.start + rest.len().min(room as usize);
+            match (&chunk, self.states) {
+                (Some(chunk), _) => self.run(|| Job::Write {
+                    chunk: Arc::clone(chunk),
+                    range: piece.clone(),
+                    position,
+                })?,
+                (None, [state]) => lock(state).write_chunk(&buf[piece.clone()], position),
+                (None, _) => unreachable!("several targets always have workers"),
+            }
+            if self.states.iter().all(|state| lock(state).error.is_some()) {
+                return Err(io::Error::other("every target failed"));
+            }
+            self.range_written += piece.len() as u64;
+            self.position = position + piece.len() as u64;
+            rest.start = piece.end;
+        }
+        WRITTEN.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        show_flush_progress(self.flush_notice, || {
+            result = match self.states {
+                [state] if self.workers.is_empty() => {
+                    lock(state).finish();
+                    Ok(())
+                }
+                _ => self.run(|| Job::Finish),
+            }
+        });
+        result
+    }
+}
+
+// --timeout: watch WRITTEN from another thread, since a write stuck in the kernel never
+// gets back to the copy loop to notice. After `timeout` without movement the copy is asked
+// to stop, flushing what it has; if it is still stuck STALL_GRACE later, mkdev exits without
+// it. Dropping the returned sender, once the copy is over, ends the watch.
+fn watch_progress(timeout: Duration) -> mpsc::Sender<()> {
+    let (done, finished) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        let mut last = WRITTEN.load(Ordering::Relaxed);
+        let mut moved = Instant::now();
+        while moved.elapsed() < timeout {
+            if finished.recv_timeout(WATCHDOG_INTERVAL) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            let written = WRITTEN.load(Ordering::Relaxed);
+            if written != last {
+                last = written;
+                moved = Instant::now();
+            }
+        }
+
+        STALLED.store(true, Ordering::Relaxed);
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        warn!(
+            "\n⏱️  No progress for {}: the write stalled {} bytes ({:.2} MB) into the image",
+            format_duration(timeout),
+            last,
+            last as f64 / 1_000_000.0
+        );
+        if finished.recv_timeout(STALL_GRACE) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+        eprintln!(
+            "Error: The write is still stuck after another {}; exiting without a final flush",
+            format_duration(STALL_GRACE)
+        );
+        Exit::Stalled.exit();
+    });
+    done
+}
+
+// Read the source once and write each buffer to every target in parallel, at the offsets
+// `bmap` maps them to if given. Returns the bytes written per target, or that target's error.
+// Without a known `total_size` (stdin, compressed sources) the progress lines show only bytes
+// written and speed. Transient write errors are retried up to `max_retries` times per buffer.
+fn copy_with_progress<R: Read + ?Sized>(
+    source: &mut R,
+    targets: &mut [Target],
+    bmap: Option<&BlockMap>,
+    total_size: Option<u64>,
+    writer: &Writer,
+    max_retries: u32,
+    progress_format: ProgressFormat,
+) -> io::Result<Vec<io::Result<u64>>> {
+    let rows = targets.len();
+    let mut reporters: Vec<Box<dyn ProgressReporter>> = targets
+        .iter()
+        .enumerate()
+        .map(|(row, target)| -> Box<dyn ProgressReporter> {
+            match progress_format {
+                ProgressFormat::Human if rows == 1 => human_reporter("📝 Progress", 0, 1),
+                ProgressFormat::Human => human_reporter(&format!("📝 {}", target.path), row, rows),
+                ProgressFormat::Json => Box::new(JsonReporter::new().with_target(&target.path)),
+                ProgressFormat::Quiet => Box::new(NullReporter),
+            }
+        })
+        .collect();
+    if progress_format == ProgressFormat::Human && rows > 1 && !output::plain() {
+        print!("{}", "\n".repeat(rows));
+    }
+    let flush_notice = match progress_format {
+        ProgressFormat::Human if !io::stdout().is_terminal() => FlushNotice::Line,
+        ProgressFormat::Human if !output::plain() => FlushNotice::Spinner,
+        _ => FlushNotice::None,
+    };
+
+    let multiple = targets.len() > 1;
+    let states: Vec<Mutex<WriteState>> = targets
+        .iter_mut()
+        .map(|target| {
+            Mutex::new(WriteState {
+                target,
+                written: 0,
+                position: 0,
+                cache_dropped_until: 0,
+                synced_until: 0,
+                write_time: Duration::ZERO,
+                error: None,
+                max_retries,
+                retries: 0,
+                skipped: 0,
+                skipped_until: 0,
+            })
+        })
+        .collect();
+
+    let mut read_rate = 0.0;
+    // For the summary: the speed over each second, and the pass as a whole once it's synced
+    let mut speeds = SpeedRange::new();
+    let mut finished = None;
+    let direct_io: Vec<bool> = states
+        .iter()
+        .map(|state| lock(state).target.direct_io)
+        .collect();
+
+    // Each target's reporter sees its own byte count, and a failed target's one last update
+    // with the error
+    let mut failures_reported = vec![false; rows];
+    let mut last_progress = Progress::default();
+    // The workers end with the scope, once the copy drops their senders
+    let result = thread::scope(|scope| {
+        let workers = match states.as_slice() {
+            [_] => Vec::new(),
+            states => states
+                .iter()
+                .map(|state| TargetWorker::spawn(scope, state))
+                .collect(),
+        };
+        writer.copy(
+            source,
+            &mut FanOut {
+                states: &states,
+                workers,
+                chunk: None,
+                bmap,
+                range: 0,
+                range_written: 0,
+                position: 0,
+                flush_notice,
+            },
+            total_size,
+            &mut |progress: &Progress| {
+                speeds.sample(progress.bytes_written, progress.elapsed);
+                if progress.done {
+                    read_rate = rate_mbps(progress.bytes_written, progress.read_time);
+                    finished = Some(progress.clone());
+                }
+                report_progress(
+                    &states,
+                    &mut reporters,
+                    &mut failures_reported,
+                    progress,
+                    false,
+                );
+                last_progress = progress.clone();
+            },
+        )
+    });
+
+    // A target that failed after the last update, or with it the whole write
+    report_progress(
+        &states,
+        &mut reporters,
+        &mut failures_reported,
+        &last_progress,
+        true,
+    );
+    let states: Vec<WriteState> = states
+        .into_iter()
+        .map(|state| state.into_inner().unwrap_or_else(|e| e.into_inner()))
+        .collect();
+    match result {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+            let synced = states.iter().map(|state| state.written).max().unwrap_or(0);
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!(
+                    "{} bytes ({:.2} MB) were synced to the device before stopping",
+                    synced,
+                    synced as f64 / 1_000_000.0
+                ),
+            ));
+        }
+        // Every target failed; each one's error is reported below
+        Err(_) if states.iter().all(|state| state.error.is_some()) => {}
+        Err(e) => return Err(e),
+    }
+    for reporter in reporters.iter_mut() {
+        reporter.finish();
+    }
+
+    let write_rates: Vec<String> = states
+        .iter()
+        .filter(|state| state.error.is_none())
+        .map(|state| {
+            let rate = rate_mbps(state.written - state.skipped, state.write_time);
+            if multiple {
+                format!("{} {:.2} MB/s", state.target.path, rate)
+            } else {
+                format!("{:.2} MB/s", rate)
+            }
+        })
+        .collect();
+    if !write_rates.is_empty() {
+        say!(
+            "📊 Read: {:.2} MB/s | Write: {}",
+            read_rate,
+            write_rates.join(", ")
+        );
+    }
+    if let Some(finished) = finished.filter(|_| !write_rates.is_empty()) {
+        print_summary(&finished, &speeds, &states, &direct_io, progress_format);
+    }
+    for state in states.iter().filter(|state| state.skipped > 0) {
+        say!(
+            "⏭️  {}: left out {:.2} MB of zero blocks",
+            state.target.path,
+            state.skipped as f64 / 1_000_000.0
+        );
+    }
+    for state in states.iter().filter(|state| state.retries > 0) {
+        warn!(
+            "🔁 {}: {} write {} retried; the device may be failing",
+            state.target.path,
+            state.retries,
+            if state.retries == 1 { "was" } else { "s were" }
+        );
+    }
+
+    Ok(states
+        .into_iter()
+        .map(|state| match state.error {
+            Some(e) => Err(e),
+            None => Ok(state.written),
+        })
+        .collect())
+}
+
+// Hand each target's reporter its share of `progress`, or only the failed targets' with
+// `failures_only`. Failed targets get one update with the error and none after it.
+fn report_progress(
+    states: &[Mutex<WriteState>],
+    reporters: &mut [Box<dyn ProgressReporter>],
+    failures_reported: &mut [bool],
+    progress: &Progress,
+    failures_only: bool,
+) {
+    for ((state, reporter), reported) in states
+        .iter()
+        .zip(reporters.iter_mut())
+        .zip(failures_reported.iter_mut())
+    {
+        let state = lock(state);
+        let error = state.error.as_ref().map(|e| e.to_string());
+        if *reported || (failures_only && error.is_none()) {
+            continue;
+        }
+        *reported = error.is_some();
+        reporter.update(&Progress {
+            bytes_written: state.written,
+            write_time: state.write_time,
+            error,
+            ..progress.clone()
+        });
+    }
+}
+
+// A few numbers to compare sticks and runs by: how long the write took including the final
+// sync, the range of its speed second by second, and whether the page cache was bypassed.
+// Under --progress=json the same goes to stderr as one line, e.g.
+// {"summary":true,"duration_s":412.30,"bytes_written":4932407296,"min_speed_mbps":8.12,
+//  "avg_speed_mbps":11.96,"max_speed_mbps":38.40,"direct_io":{"/dev/sdc":true}}
+// The minimum and maximum are null for a write shorter than a second.
+fn print_summary(
+    finished: &Progress,
+    speeds: &SpeedRange,
+    states: &[WriteState],
+    direct_io: &[bool],
+    progress_format: ProgressFormat,
+) {
+    let written: Vec<(&str, bool)> = states
+        .iter()
+        .zip(direct_io)
+        .filter(|(state, _)| state.error.is_none())
+        .map(|(state, &direct_io)| (state.target.path.as_str(), direct_io))
+        .collect();
+    let range = speeds.range_mbps();
+    match range {
+        Some((min, max)) => say!(
+            "📈 Speed: {:.2} MB/s on average, {:.2} to {:.2} MB/s second by second, over {}",
+            finished.speed_mbps(),
+            min,
+            max,
+            format_duration(finished.elapsed)
+        ),
+        None => say!(
+            "📈 Speed: {:.2} MB/s on average, over {}",
+            finished.speed_mbps(),
+            format_duration(finished.elapsed)
+        ),
+    }
+    let on_off = |direct_io: bool| if direct_io { "on" } else { "off" };
+    if written
+        .iter()
+        .all(|&(_, direct_io)| direct_io == written[0].1)
+    {
+        say!("⚙️  Direct I/O: {}", on_off(written[0].1));
+    } else {
+        let each: Vec<String> = written
+            .iter()
+            .map(|&(path, direct_io)| format!("{} {}", path, on_off(direct_io)))
+            .collect();
+        say!("⚙️  Direct I/O: {}", each.join(", "));
+    }
+
+    if progress_format == ProgressFormat::Json {
+        let or_null =
+            |value: Option<f64>| value.map_or("null".to_string(), |v| format!("{:.2}", v));
+        let direct_io: Vec<String> = written
+            .iter()
+            .map(|&(path, direct_io)| format!("{}:{}", json_string(path), direct_io))
+            .collect();
+        eprintln!(
+            "{{\"summary\":true,\"duration_s\":{:.2},\"bytes_written\":{},\"min_speed_mbps\":{},\"avg_speed_mbps\":{:.2},\"max_speed_mbps\":{},\"direct_io\":{{{}}}}}",
+            finished.elapsed.as_secs_f64(),
+            finished.bytes_written,
+            or_null(range.map(|(min, _)| min)),
+            finished.speed_mbps(),
+            or_null(range.map(|(_, max)| max)),
+            direct_io.join(",")
+        );
+    }
+}
+
+// Hash the first `total_size` bytes of the source and compare against the expected hex digest
+fn verify_checksum(
+    source: &mut SourceFile,
+    total_size: u64,
+    buffer_size: usize,
+    algo: ChecksumAlgo,
+    expected: &str,
+    progress_format: ProgressFormat,
+) -> io::Result<bool> {
+    source.seek(SeekFrom::Start(0))?;
+
+    let mut hasher = algo.hasher();
+    let mut buffer = vec![0u8; buffer_size];
+    let mut total_hashed = 0u64;
+    let start_time = Instant::now();
+    let mut last_update = Instant::now();
+    let mut reporter: Box<dyn ProgressReporter> = match progress_format {
+        ProgressFormat::Human => human_reporter("🔐 Hashing", 0, 1),
+        ProgressFormat::Json => Box::new(JsonReporter::new().with_phase("checksum")),
+        ProgressFormat::Quiet => Box::new(NullReporter),
+    };
+
+    while total_hashed < total_size {
+        let to_read = buffer_size.min((total_size - total_hashed) as usize);
+        source.read_exact(&mut buffer[..to_read])?;
+        hasher.update(&buffer[..to_read]);
+        total_hashed += to_read as u64;
+
+        let done = total_hashed == total_size;
+        if last_update.elapsed().as_millis() >= 100 || done {
+            let elapsed = start_time.elapsed();
+            reporter.update(&Progress {
+                bytes_written: total_hashed,
+                total: Some(total_size),
+                elapsed,
+                read_time: elapsed,
+                write_time: Duration::ZERO,
+                recent_speed_mbps: None,
+                done,
+                error: None,
+            });
+            last_update = Instant::now();
+        }
+    }
+    reporter.finish();
+
+    source.seek(SeekFrom::Start(0))?;
+
+    Ok(report_checksum(algo, &to_hex(&hasher.finalize()), expected))
+}
+
+fn report_checksum(algo: ChecksumAlgo, computed: &str, expected: &str) -> bool {
+    if computed == expected {
+        return true;
+    }
+    warn!("\n❌ {} mismatch:", algo.name());
+    eprintln!("  Expected: {}", expected);
+    eprintln!("  Computed: {}", computed);
+    false
+}
+
+// Clear the device from `start` to its end: a discard where the device supports it,
+// otherwise write zeros. Returns how it was done and how many bytes were cleared.
+fn wipe_after(target: &mut Target, start: u64, buffer_size: usize) -> io::Result<(Wipe, u64)> {
+    let end = System.device_size(&target.file)?;
+    if start >= end {
+        return Ok((Wipe::Discarded, 0));
+    }
+    let length = end - start;
+
+    match System.discard(&target.file, start, length) {
+        Ok(()) => return Ok((Wipe::Discarded, length)),
+        // The image rarely ends on a block boundary: discard the whole blocks and zero the
+        // partial ones on either side
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            let block_size = System.logical_block_size(&target.file)?;
+            let aligned_start = start.div_ceil(block_size) * block_size;
+            let aligned_end = end / block_size * block_size;
+            if aligned_start < aligned_end
+                && System
+                    .discard(&target.file, aligned_start, aligned_end - aligned_start)
+                    .is_ok()
+            {
+                zero_range(target, start, aligned_start, buffer_size)?;
+                zero_range(target, aligned_end, end, buffer_size)?;
+                return Ok((Wipe::Discarded, length));
+            }
+        }
+        Err(_) => {}
+    }
+
+    zero_range(target, start, end, buffer_size)?;
+    Ok((Wipe::Zeroed, length))
+}
+
+// Write zeros over `start..end` of the target, through the regular path since the range
+// needn't be aligned
+fn zero_range(target: &mut Target, start: u64, end: u64, buffer_size: usize) -> io::Result<()> {
+    if start >= end {
+        return Ok(());
+    }
+    if target.direct_io {
+        System.set_direct_io(&mut target.file, false)?;
+        target.direct_io = false;
+    }
+    let zeros = vec![0u8; buffer_size.min((end - start) as usize)];
+    target.file.seek(SeekFrom::Start(start))?;
+    let mut remaining = end - start;
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        target.file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    target.file.sync_all()
+}
+
+// Make the target read back zeros from its offset on, so --sparse can leave blocks of zeros
+// out. A block device is discarded up to the end of the image, or to its own end when the
+// image size isn't known; a file is cut off at the offset and grows holes where the zeros
+// would go. Returns how far past the offset the zeros reach, 0 if the target can't do it.
+fn prepare_sparse(target: &mut Target, image_size: Option<u64>) -> u64 {
+    if !target.block_device {
+        return match target.file.set_len(target.offset) {
+            Ok(()) => u64::MAX,
+            Err(e) => {
+                warn!(
+                    "⚠️  Warning: Cannot truncate {} for --sparse ({}); writing every block",
+                    target.path, e
+                );
+                0
+            }
+        };
+    }
+
+    let end = match image_size {
+        Some(size) => Ok(target.offset + size),
+        None => System.device_size(&target.file),
+    };
+    // A partial block at the end can't be discarded, so it gets written whatever it holds
+    let length = System
+        .logical_block_size(&target.file)
+        .and_then(|block_size| {
+            end.map(|end| end.saturating_sub(target.offset) / block_size * block_size)
+        });
+    match length {
+        Ok(length) if System.discard(&target.file, target.offset, length).is_ok() => {
+            say!(
+                "✂️  Discarded {:.2} MB of {}; blocks of zeros will be left out",
+                length as f64 / 1_000_000.0,
+                target.path
+            );
+            length
+        }
+        Ok(_) => {
+            warn!(
+                "⚠️  Warning: {} does not support discard; writing every block",
+                target.path
+            );
+            0
+        }
+        Err(e) => {
+            warn!(
+                "⚠️  Warning: Cannot size {} for --sparse ({}); writing every block",
+                target.path, e
+            );
+            0
+        }
+    }
+}
+
+fn rate_mbps(bytes: u64, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / secs / 1_000_000.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    // Bytes that differ from one position to the next, so a misplaced write shows
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u32).wrapping_mul(2_654_435_761).to_le_bytes()[3] ^ seed)
+            .collect()
+    }
+
+    // Run the buffer size benchmark for an image of `image_len` bytes written at `offset`
+    // into a file holding `existing`, and return what the file holds afterwards
+    fn benchmark_file(name: &str, existing: &[u8], image_len: usize, offset: u64) -> Vec<u8> {
+        let dir = env::temp_dir();
+        let source_path = dir.join(format!("mkdev-test-{}-{}.img", process::id(), name));
+        let target_path = dir.join(format!("mkdev-test-{}-{}.target", process::id(), name));
+        fs::write(&source_path, pattern(image_len, 0x5a)).unwrap();
+        fs::write(&target_path, existing).unwrap();
+
+        let target_str = target_path.to_str().unwrap();
+        let (file, direct_io) = System.open_target(target_str, true).unwrap();
+        let mut target = Target {
+            path: target_str.to_string(),
+            file,
+            offset,
+            direct_io,
+            drop_cache: false,
+            sync_interval: None,
+            block_device: false,
+            zeroed_until: 0,
+            checkpoint: None,
+        };
+        let mut source = SourceFile::new(vec![File::open(&source_path).unwrap()]).unwrap();
+        let result = detect_optimal_buffer_size(&mut source, None, &mut target, 0);
+        drop(target);
+        let after = fs::read(&target_path).unwrap();
+        fs::remove_file(&source_path).ok();
+        fs::remove_file(&target_path).ok();
+        assert!(BENCHMARK_BUFFER_SIZES.contains(&result.unwrap()));
+        after
+    }
+
+    #[test]
+    fn benchmark_restores_the_target() {
+        let existing = pattern(256 * 1024, 0xa5);
+        assert_eq!(
+            benchmark_file("inside", &existing, 64 * 1024, 8192),
+            existing
+        );
+    }
+
+    #[test]
+    fn benchmark_restores_a_region_off_block_boundaries() {
+        // The file ends 1808 bytes into a block, inside the benchmarked region
+        let existing = pattern(10_000, 0xa5);
+        assert_eq!(benchmark_file("tail", &existing, 64 * 1024, 0), existing);
+        // And an empty file stays empty
+        assert_eq!(benchmark_file("empty", &[], 64 * 1024, 0), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn benchmark_leaves_the_file_around_the_image_alone() {
+        // An image whose length isn't a whole number of blocks, written past the start and
+        // ending before the end of the file
+        let existing = pattern(MB, 0xa5);
+        assert_eq!(
+            benchmark_file("around", &existing, 40_000, 12_288),
+            existing
+        );
+    }
+
+    #[test]
+    fn buffer_size_flag_wins_over_env() {
+        assert_eq!(
+            buffer_size_setting(Some("8"), Some("4M")),
+            Ok(Some((8 * MB, BufferSizeFrom::Flag)))
+        );
+        assert_eq!(
+            buffer_size_setting(Some("512K"), None),
+            Ok(Some((512 * 1024, BufferSizeFrom::Flag)))
+        );
+        assert_eq!(
+            buffer_size_setting(None, Some("4M")),
+            Ok(Some((4 * MB, BufferSizeFrom::Env)))
+        );
+        // An empty variable is as good as none
+        assert_eq!(buffer_size_setting(None, Some("")), Ok(None));
+        assert_eq!(buffer_size_setting(None, None), Ok(None));
+    }
+
+    #[test]
+    fn buffer_size_invalid_settings() {
+        assert!(buffer_size_setting(Some("0"), None).is_err());
+        assert!(buffer_size_setting(Some("16MB"), None).is_err());
+        // A bad variable is an error, naming the variable, unless the flag overrides it
+        let error = buffer_size_setting(None, Some("lots")).unwrap_err();
+        assert!(error.contains(BUFFER_SIZE_VAR), "{}", error);
+        assert!(buffer_size_setting(Some("8"), Some("lots")).is_ok());
+    }
+
+    #[test]
+    fn buffer_size_precedence() {
+        let given = Some((8 * MB, BufferSizeFrom::Env));
+        let detect_unused = || -> Option<usize> { panic!("auto-detection ran") };
+        assert_eq!(
+            choose_buffer_size(given, detect_unused),
+            (8 * MB, BufferSizeFrom::Env)
+        );
+        assert_eq!(
+            choose_buffer_size(None, || Some(32 * MB)),
+            (32 * MB, BufferSizeFrom::Detected)
+        );
+        assert_eq!(
+            choose_buffer_size(None, || None),
+            (DEFAULT_BUFFER_SIZE, BufferSizeFrom::Default)
+        );
+    }
+
+    #[test]
+    fn image_signatures() {
+        let sector = ISO_SECTOR_SIZE as usize;
+        let mut header = vec![0u8; SIGNATURE_PROBE_SIZE];
+        let found = ImageSignatures::of(&header);
+        assert!(!found.mbr && !found.gpt && !found.iso9660 && !found.el_torito);
+
+        header[510..512].copy_from_slice(&[0x55, 0xAA]);
+        header[512..520].copy_from_slice(b"EFI PART");
+        header[16 * sector + 1..16 * sector + 6].copy_from_slice(b"CD001");
+        header[17 * sector + 1..17 * sector + 6].copy_from_slice(b"CD001");
+        header[17 * sector + 7..17 * sector + 30].copy_from_slice(b"EL TORITO SPECIFICATION");
+        let found = ImageSignatures::of(&header);
+        assert!(found.mbr && found.gpt && found.iso9660 && found.el_torito);
+
+        // Cut off partway through a record
+        let found = ImageSignatures::of(&header[..17 * sector + 20]);
+        assert!(found.iso9660 && !found.el_torito);
+        assert!(!ImageSignatures::of(&header[..511]).mbr);
+
+        // A data ISO: a primary volume descriptor but nothing to boot from
+        header[..520].fill(0);
+        header[17 * sector..18 * sector].fill(0);
+        let found = ImageSignatures::of(&header);
+        assert!(found.iso9660 && !found.mbr && !found.gpt && !found.el_torito);
+    }
 }
@@ -0,0 +1,110 @@
+// --notify: a desktop notification once the write is over, for writes left to run while the
+// user is elsewhere. Sent through notify-send, which speaks the freedesktop
+// org.freedesktop.Notifications D-Bus interface to whatever notification daemon the desktop
+// runs. Armed when the write starts, so every way out from there on (success, a failed
+// write or verify, a stall, Ctrl-C) sends exactly one.
+
+use mkdev::format_duration;
+use output::warn;
+use std::sync::Mutex;
+use std::time::Instant;
+
+struct Pending {
+    source: String,
+    targets: String,
+    started: Instant,
+}
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+pub fn arm(source: &str, targets: &str) {
+    let pending = Pending {
+        source: source.to_string(),
+        targets: targets.to_string(),
+        started: Instant::now(),
+    };
+    *PENDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(pending);
+}
+
+// Send the notification if one is armed: `failure` is None on success, or what went wrong
+pub fn send(failure: Option<&str>) {
+    let Some(pending) = PENDING.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    let elapsed = format_duration(pending.started.elapsed());
+    let (summary, body) = match failure {
+        None => (
+            "mkdev: write finished".to_string(),
+            format!(
+                "{} was written to {} in {}",
+                pending.source, pending.targets, elapsed
+            ),
+        ),
+        Some(failure) => (
+            format!("mkdev: {}", failure),
+            format!(
+                "Writing {} to {} ended after {}: {}",
+                pending.source, pending.targets, elapsed, failure
+            ),
+        ),
+    };
+    if let Err(e) = notify_send(&summary, &body, failure.is_some()) {
+        warn!("⚠️  Warning: Cannot send the --notify notification: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn notify_send(summary: &str, body: &str, urgent: bool) -> Result<(), String> {
+    use std::env;
+    use std::io;
+    use std::process::{Command, Stdio};
+
+    let urgency = if urgent { "critical" } else { "normal" };
+    let args = [
+        "--app-name=mkdev",
+        "--icon=drive-removable-media",
+        &format!("--urgency={}", urgency),
+        "--",
+        summary,
+        body,
+    ];
+    // Under sudo the root user has no desktop session; deliver it to the session bus of
+    // the user who ran sudo instead
+    let sudo_uid = env::var("SUDO_UID")
+        .ok()
+        .filter(|_| unsafe { libc::geteuid() } == 0);
+    let mut command = match sudo_uid {
+        Some(uid) => {
+            let mut command = Command::new("sudo");
+            command.args([
+                "-u".to_string(),
+                format!("#{}", uid),
+                "env".to_string(),
+                format!("DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{}/bus", uid),
+                "notify-send".to_string(),
+            ]);
+            command
+        }
+        None => Command::new("notify-send"),
+    };
+    let status = command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => "notify-send was not found (libnotify-bin)".to_string(),
+            _ => e.to_string(),
+        })?;
+    if !status.success() {
+        return Err(format!("notify-send {}", status));
+    }
+    Ok(())
+}
+
+// There is no notify-send, nor a D-Bus session, on Windows
+#[cfg(windows)]
+fn notify_send(_summary: &str, _body: &str, _urgent: bool) -> Result<(), String> {
+    Err("desktop notifications are only supported on Linux".to_string())
+}
@@ -0,0 +1,133 @@
+// --log: a JSON-lines audit trail of every write, appended to at the end of the run. One
+// line per target, e.g.
+// {"timestamp":"2026-01-31T09:15:02Z","source":"ubuntu.iso","target":"/dev/sdc","size":4932407296,
+//  "bytes_written":4932407296,"buffer_size":16777216,"duration_s":412.30,"speed_mbps":11.96,
+//  "direct_io":true,"verified":true,"digest_algo":null,"source_digest":null,"device_digest":null,
+//  "result":"success","error":null}
+// `size` is null when it isn't known up front, `bytes_written` when the write was cut short
+// for every target at once and `verified` when --verify wasn't given. The digests are only
+// there with --hash-both, `device_digest` when --verify read the device back; `digest_algo`
+// says which they are, e.g. "SHA256".
+
+use mkdev::json_string;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct OperationLog {
+    file: File,
+}
+
+// What happened to one target
+pub struct Entry<'a> {
+    pub started: SystemTime,
+    pub source: &'a str,
+    pub target: &'a str,
+    pub size: Option<u64>,
+    pub bytes_written: Option<u64>,
+    pub buffer_size: usize,
+    pub duration: Duration,
+    pub direct_io: bool,
+    pub verified: Option<bool>,
+    pub digest_algo: Option<&'a str>,
+    pub source_digest: Option<&'a str>,
+    pub device_digest: Option<&'a str>,
+    // "success", "failure", "stalled" or "interrupted"
+    pub result: &'a str,
+    pub error: Option<String>,
+}
+
+impl OperationLog {
+    // Opened up front so a bad path fails before anything is written
+    pub fn open(path: &Path) -> io::Result<OperationLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(OperationLog { file })
+    }
+
+    // Each line goes out in a single write, so concurrent runs appending to the same log
+    // don't interleave
+    pub fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let secs = entry.duration.as_secs_f64();
+        let speed = match entry.bytes_written {
+            Some(bytes) if secs > 0.0 => bytes as f64 / secs / 1_000_000.0,
+            _ => 0.0,
+        };
+        let line = format!(
+            "{{\"timestamp\":{},\"source\":{},\"target\":{},\"size\":{},\"bytes_written\":{},\"buffer_size\":{},\"duration_s\":{:.2},\"speed_mbps\":{:.2},\"direct_io\":{},\"verified\":{},\"digest_algo\":{},\"source_digest\":{},\"device_digest\":{},\"result\":{},\"error\":{}}}\n",
+            json_string(&utc_timestamp(entry.started)),
+            json_string(entry.source),
+            json_string(entry.target),
+            or_null(entry.size.map(|size| size.to_string())),
+            or_null(entry.bytes_written.map(|bytes| bytes.to_string())),
+            entry.buffer_size,
+            secs,
+            speed,
+            entry.direct_io,
+            or_null(entry.verified.map(|verified| verified.to_string())),
+            or_null(entry.digest_algo.map(json_string)),
+            or_null(entry.source_digest.map(json_string)),
+            or_null(entry.device_digest.map(json_string)),
+            json_string(entry.result),
+            or_null(entry.error.as_deref().map(json_string)),
+        );
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+// RFC 3339 in UTC, e.g. 2026-01-31T09:15:02Z
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let (days, time_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> String {
+        utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn utc_timestamps() {
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(1_709_210_096), "2024-02-29T12:34:56Z");
+        assert_eq!(at(1_709_251_200), "2024-03-01T00:00:00Z");
+        assert_eq!(at(1_704_067_199), "2023-12-31T23:59:59Z");
+        assert_eq!(at(1_704_067_200), "2024-01-01T00:00:00Z");
+        // Centuries are leap years only every 400 years
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(4_107_542_400), "2100-03-01T00:00:00Z");
+    }
+}
@@ -0,0 +1,102 @@
+// Everything mkdev does to a target that depends on the OS: opening it with the page cache
+// bypassed, telling disks from partitions and regular files, finding what is mounted from
+// them and stopping cleanly on Ctrl-C. Linux goes through O_DIRECT, ioctls and sysfs; Windows
+// through raw \\.\PhysicalDriveN handles and DeviceIoControl; other unix systems get an
+// O_SYNC open and stat, without the mount and disk checks. The backend is picked at compile
+// time, so the copy loop itself is the same everywhere.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(all(unix, not(target_os = "linux")))]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(target_os = "linux")]
+pub use linux::Linux as System;
+#[cfg(all(unix, not(target_os = "linux")))]
+pub use unix::Unix as System;
+#[cfg(windows)]
+pub use windows::Windows as System;
+
+// A disk as listed by --list
+pub struct Disk {
+    pub path: String,
+    pub size: u64,
+    pub model: String,
+    pub removable: bool,
+    // Where the running system is mounted from, if this is the disk it lives on
+    pub hosts: Option<String>,
+}
+
+pub trait Platform {
+    // A disk or partition a target path resolves to
+    type Device;
+
+    // Whether open_target takes mounted filesystems off the device itself, so being mounted
+    // is not a reason to refuse it
+    const DISMOUNTS_ON_OPEN: bool;
+    // Whether mounted_filesystems and is_removable find anything out. Where they can't, a
+    // device target is written with a warning that it wasn't checked.
+    const CHECKS_MOUNTS: bool;
+
+    // Open the target for writing with the page cache bypassed where the target allows it,
+    // and writes reaching the device before they return. Returns whether direct I/O is on.
+    fn open_target(&self, path: &str, readable: bool) -> io::Result<(File, bool)>;
+    // Direct I/O needs aligned transfers, so it is turned off for the odd-sized tail and for
+    // reading back. May replace the handle; the file position is kept.
+    fn set_direct_io(&self, file: &mut File, enabled: bool) -> io::Result<()>;
+
+    // None for anything that isn't a disk or partition, e.g. an image file
+    fn block_device(&self, path: &Path) -> Option<Self::Device>;
+    fn is_block_device(&self, file: &File) -> bool;
+    // Whether the device refuses writes: a locked SD card, or a disk set read-only. False
+    // for anything that isn't a disk or partition.
+    fn is_write_protected(&self, path: &Path) -> io::Result<bool>;
+    // The whole disk the device is on, as a path to show the user
+    fn disk_path(&self, device: &Self::Device) -> String;
+    // (mount source, mountpoint) for every filesystem mounted from the device, or from its
+    // partitions if it is a whole disk
+    fn mounted_filesystems(&self, device: &Self::Device) -> Vec<(String, String)>;
+    fn is_removable(&self, device: &Self::Device) -> bool;
+    // Whether the device is a partition rather than the whole disk it is on
+    fn is_partition(&self, device: &Self::Device) -> bool;
+    // Whether writing to `target` would overwrite `source` as it is read: the same file or
+    // device under another name, a disk and one of its partitions, or a file kept on a
+    // filesystem on the target
+    fn overwrites(&self, target: &Path, source: &Path) -> bool;
+    // Physical disks that could be written to
+    fn disks(&self) -> io::Result<Vec<Disk>>;
+
+    // Size of a block device in bytes
+    fn device_size(&self, file: &File) -> io::Result<u64>;
+    // The smallest unit a block device can be written in, usually 512 or 4096 bytes
+    fn logical_block_size(&self, file: &File) -> io::Result<u64>;
+    // The (minimum, optimal) transfer sizes a block device reports, in bytes; 0 for either
+    // when the device doesn't say
+    fn io_sizes(&self, file: &File) -> io::Result<(u64, u64)>;
+    // Bytes a regular file target can hold: its current length plus the free space left on
+    // its filesystem, since the image overwrites the file in place
+    fn file_capacity(&self, file: &File, path: &Path) -> io::Result<u64>;
+    // Tell the device the range no longer holds data. An error if it can't, so the caller
+    // writes zeros instead; InvalidInput when the range isn't on logical block boundaries.
+    fn discard(&self, file: &File, start: u64, length: u64) -> io::Result<()>;
+    // Evict the written range from the page cache
+    fn drop_written_pages(&self, file: &File, start: u64, end: u64) -> io::Result<()>;
+    // Flush and evict everything cached for the target, so that reading it back comes from
+    // the media rather than from memory
+    fn drop_cache(&self, file: &File) -> io::Result<()>;
+
+    // While enabled, Ctrl-C and termination requests only set INTERRUPTED so the copy loop
+    // can stop at a buffer boundary and sync what it has written
+    fn set_interrupt_handler(&self, enabled: bool);
+    // Errors worth retrying a write for. Running out of space, a read-only device and the
+    // like won't go away by themselves.
+    fn is_transient(&self, error: &io::Error) -> bool;
+    // The terminal, to ask for confirmation on when stdin is carrying the image
+    fn console(&self) -> io::Result<File>;
+}
@@ -0,0 +1,430 @@
+// O_DIRECT and block-device ioctls for writing, sysfs and mountinfo for finding out what a
+// target is
+
+use super::{Disk, Platform};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use libc::{BLKIOMIN, BLKIOOPT, BLKSSZGET};
+
+// Block device ioctls from <linux/fs.h> that the libc crate doesn't export
+const BLKGETSIZE64: libc::c_ulong = 0x8008_1272; // _IOR(0x12, 114, size_t)
+const BLKDISCARD: libc::c_ulong = 0x1277; // _IO(0x12, 119)
+const BLKROGET: libc::c_ulong = 0x125e; // _IO(0x12, 94)
+const BLKFLSBUF: libc::c_ulong = 0x1261; // _IO(0x12, 97)
+
+pub struct Linux;
+
+// A block device as seen through sysfs, e.g. `sdc1` on disk `sdc`
+pub struct BlockDevice {
+    name: String,
+    disk: String,
+    dev: (u32, u32),
+}
+
+impl Platform for Linux {
+    type Device = BlockDevice;
+
+    const DISMOUNTS_ON_OPEN: bool = false;
+    const CHECKS_MOUNTS: bool = true;
+
+    // Prefer O_DIRECT | O_SYNC, falling back to O_SYNC, then to a plain open, for targets
+    // that don't support them
+    fn open_target(&self, path: &str, readable: bool) -> io::Result<(File, bool)> {
+        let open = |flags: i32| {
+            OpenOptions::new()
+                .read(readable)
+                .write(true)
+                .custom_flags(flags)
+                .open(path)
+        };
+
+        if let Ok(file) = open(libc::O_DIRECT | libc::O_SYNC) {
+            return Ok((file, true));
+        }
+        if let Ok(file) = open(libc::O_SYNC) {
+            return Ok((file, false));
+        }
+        open(0).map(|file| (file, false))
+    }
+
+    fn set_direct_io(&self, file: &mut File, enabled: bool) -> io::Result<()> {
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let flags = if enabled {
+                flags | libc::O_DIRECT
+            } else {
+                flags & !libc::O_DIRECT
+            };
+            if libc::fcntl(fd, libc::F_SETFL, flags) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // Resolve a target path (following symlinks such as /dev/disk/by-id) to its sysfs identity
+    fn block_device(&self, path: &Path) -> Option<BlockDevice> {
+        let metadata = fs::metadata(path).ok()?;
+        if !metadata.file_type().is_block_device() {
+            return None;
+        }
+
+        let rdev = metadata.rdev();
+        block_device_from_number((libc::major(rdev), libc::minor(rdev)))
+    }
+
+    fn is_block_device(&self, file: &File) -> bool {
+        file.metadata()
+            .map(|m| m.file_type().is_block_device())
+            .unwrap_or(false)
+    }
+
+    // The kernel marks a card read-only when the reader reports its write-protect switch
+    // as on, and BLKROGET reads that flag back
+    fn is_write_protected(&self, path: &Path) -> io::Result<bool> {
+        let file = File::open(path)?;
+        if !self.is_block_device(&file) {
+            return Ok(false);
+        }
+        let mut read_only: libc::c_int = 0;
+        if unsafe { libc::ioctl(file.as_raw_fd(), BLKROGET, &mut read_only) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read_only != 0)
+    }
+
+    fn disk_path(&self, device: &BlockDevice) -> String {
+        format!("/dev/{}", device.disk)
+    }
+
+    // mountinfo identifies mounts by device number; /proc/mounts catches any that name the
+    // node directly
+    fn mounted_filesystems(&self, device: &BlockDevice) -> Vec<(String, String)> {
+        let covered = covered_device_numbers(device);
+        let mut mounts: Vec<(String, String)> = Vec::new();
+
+        if let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo") {
+            for line in mountinfo.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 5 {
+                    continue;
+                }
+                if !parse_device_number(fields[2]).is_some_and(|dev| covered.contains(&dev)) {
+                    continue;
+                }
+                // Optional fields end with a lone "-", followed by fstype and mount source
+                let source = fields
+                    .iter()
+                    .position(|&f| f == "-")
+                    .and_then(|i| fields.get(i + 2))
+                    .unwrap_or(&fields[2]);
+                mounts.push((source.to_string(), unescape_mount_path(fields[4])));
+            }
+        }
+
+        if let Ok(proc_mounts) = fs::read_to_string("/proc/mounts") {
+            for line in proc_mounts.lines() {
+                let mut fields = line.split_whitespace();
+                let (Some(source), Some(mountpoint)) = (fields.next(), fields.next()) else {
+                    continue;
+                };
+                let mountpoint = unescape_mount_path(mountpoint);
+                if mounts.iter().any(|(_, m)| *m == mountpoint) {
+                    continue;
+                }
+                let is_covered = fs::metadata(source)
+                    .ok()
+                    .filter(|m| m.file_type().is_block_device())
+                    .is_some_and(|m| {
+                        covered.contains(&(libc::major(m.rdev()), libc::minor(m.rdev())))
+                    });
+                if is_covered {
+                    mounts.push((source.to_string(), mountpoint));
+                }
+            }
+        }
+
+        mounts
+    }
+
+    fn is_removable(&self, device: &BlockDevice) -> bool {
+        is_removable(&device.disk)
+    }
+
+    fn is_partition(&self, device: &BlockDevice) -> bool {
+        device.name != device.disk
+    }
+
+    // Device numbers say it all: the node a device is (st_rdev), and the filesystem a file is
+    // on (st_dev), which covered_device_numbers lists along with the partitions of a disk
+    fn overwrites(&self, target: &Path, source: &Path) -> bool {
+        let (Ok(target_meta), Ok(source_meta)) = (fs::metadata(target), fs::metadata(source))
+        else {
+            return false;
+        };
+        if target_meta.dev() == source_meta.dev() && target_meta.ino() == source_meta.ino() {
+            return true;
+        }
+        let Some(device) = self.block_device(target) else {
+            return false;
+        };
+        if let Some(source_device) = self.block_device(source) {
+            // Either one may be the disk the other is a partition of
+            return covered_device_numbers(&device).contains(&source_device.dev)
+                || covered_device_numbers(&source_device).contains(&device.dev);
+        }
+        let dev = source_meta.dev();
+        covered_device_numbers(&device).contains(&(libc::major(dev), libc::minor(dev)))
+    }
+
+    fn disks(&self) -> io::Result<Vec<Disk>> {
+        let mut names: Vec<String> = fs::read_dir("/sys/block")?
+            .flatten()
+            .filter(|entry| entry.path().join("device").exists())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+
+        let system = system_disks();
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                // sysfs reports sizes in 512-byte sectors regardless of the logical block size
+                let size = fs::read_to_string(format!("/sys/block/{}/size", name))
+                    .ok()
+                    .and_then(|sectors| sectors.trim().parse::<u64>().ok())
+                    .map(|sectors| sectors * 512)
+                    .unwrap_or(0);
+                let model = fs::read_to_string(format!("/sys/block/{}/device/model", name))
+                    .map(|model| model.trim().to_string())
+                    .unwrap_or_default();
+                Disk {
+                    path: format!("/dev/{}", name),
+                    size,
+                    model,
+                    removable: is_removable(&name),
+                    hosts: system.contains(&name).then(|| "/".to_string()),
+                }
+            })
+            .collect())
+    }
+
+    fn device_size(&self, file: &File) -> io::Result<u64> {
+        let mut size: u64 = 0;
+        if unsafe { libc::ioctl(file.as_raw_fd(), BLKGETSIZE64, &mut size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size)
+    }
+
+    fn logical_block_size(&self, file: &File) -> io::Result<u64> {
+        let mut size: libc::c_int = 0;
+        if unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET, &mut size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as u64)
+    }
+
+    // The same values as queue/minimum_io_size and queue/optimal_io_size in sysfs
+    fn io_sizes(&self, file: &File) -> io::Result<(u64, u64)> {
+        let mut minimum: libc::c_uint = 0;
+        let mut optimal: libc::c_uint = 0;
+        unsafe {
+            if libc::ioctl(file.as_raw_fd(), BLKIOMIN, &mut minimum) < 0
+                || libc::ioctl(file.as_raw_fd(), BLKIOOPT, &mut optimal) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok((minimum as u64, optimal as u64))
+    }
+
+    fn file_capacity(&self, file: &File, _path: &Path) -> io::Result<u64> {
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatvfs(file.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+        Ok(file.metadata()?.len() + free)
+    }
+
+    fn discard(&self, file: &File, start: u64, length: u64) -> io::Result<()> {
+        let range: [u64; 2] = [start, length];
+        if unsafe { libc::ioctl(file.as_raw_fd(), BLKDISCARD, range.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Pages must be clean before the kernel will drop them, so sync the range first
+    fn drop_written_pages(&self, file: &File, start: u64, end: u64) -> io::Result<()> {
+        file.sync_data()?;
+        let result = unsafe {
+            libc::posix_fadvise(
+                file.as_raw_fd(),
+                start as libc::off_t,
+                (end - start) as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(())
+    }
+
+    // BLKFLSBUF writes back and invalidates the device's buffer cache, but needs
+    // CAP_SYS_ADMIN; without it, and for regular files, fadvise over the whole length
+    // drops the clean pages
+    fn drop_cache(&self, file: &File) -> io::Result<()> {
+        file.sync_all()?;
+        if self.is_block_device(file) && unsafe { libc::ioctl(file.as_raw_fd(), BLKFLSBUF, 0) } == 0
+        {
+            return Ok(());
+        }
+        let result =
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(())
+    }
+
+    // SIGINT and SIGTERM keep their default action outside the write
+    // Installed without SA_RESTART, so a read blocked on stdin or a download returns EINTR
+    // and the copy loop gets to see the flag
+    fn set_interrupt_handler(&self, enabled: bool) {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = if enabled {
+            handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t
+        } else {
+            libc::SIG_DFL
+        };
+        unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+            libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+        }
+    }
+
+    fn is_transient(&self, error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(libc::EIO | libc::EAGAIN | libc::EBUSY | libc::ETIMEDOUT)
+        )
+    }
+
+    fn console(&self) -> io::Result<File> {
+        File::open("/dev/tty")
+    }
+}
+
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    crate::INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+fn block_device_from_number(dev: (u32, u32)) -> Option<BlockDevice> {
+    let sys_path = fs::canonicalize(format!("/sys/dev/block/{}:{}", dev.0, dev.1)).ok()?;
+    let name = sys_path.file_name()?.to_string_lossy().into_owned();
+
+    // Partitions live in a subdirectory of their parent disk and carry a `partition` file
+    let disk = if sys_path.join("partition").exists() {
+        sys_path
+            .parent()?
+            .file_name()?
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        name.clone()
+    };
+
+    Some(BlockDevice { name, disk, dev })
+}
+
+// Device numbers covered by writing to `device`: itself, plus its partitions if it is a whole disk
+fn covered_device_numbers(device: &BlockDevice) -> Vec<(u32, u32)> {
+    let mut numbers = vec![device.dev];
+
+    if device.name == device.disk {
+        if let Ok(entries) = fs::read_dir(format!("/sys/block/{}", device.disk)) {
+            for entry in entries.flatten() {
+                if !entry.path().join("partition").exists() {
+                    continue;
+                }
+                if let Some(dev) = fs::read_to_string(entry.path().join("dev"))
+                    .ok()
+                    .and_then(|dev| parse_device_number(dev.trim()))
+                {
+                    numbers.push(dev);
+                }
+            }
+        }
+    }
+
+    numbers
+}
+
+fn parse_device_number(value: &str) -> Option<(u32, u32)> {
+    let (major, minor) = value.split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+// The kernel escapes spaces, tabs, newlines and backslashes in mount paths as octal
+fn unescape_mount_path(path: &str) -> String {
+    path.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+// Disks backing the root filesystem, following device-mapper/md slaves down to the real disks
+fn system_disks() -> Vec<String> {
+    let Ok(root) = fs::metadata("/") else {
+        return Vec::new();
+    };
+    let dev = root.dev();
+    let Some(device) = block_device_from_number((libc::major(dev), libc::minor(dev))) else {
+        return Vec::new();
+    };
+
+    let mut disks = Vec::new();
+    let mut pending = vec![device.disk];
+    while let Some(disk) = pending.pop() {
+        let slaves: Vec<String> = fs::read_dir(format!("/sys/block/{}/slaves", disk))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        let dev = fs::read_to_string(entry.path().join("dev")).ok()?;
+                        Some(block_device_from_number(parse_device_number(dev.trim())?)?.disk)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if slaves.is_empty() {
+            if !disks.contains(&disk) {
+                disks.push(disk);
+            }
+        } else {
+            pending.extend(slaves);
+        }
+    }
+
+    disks
+}
+
+fn is_removable(disk: &str) -> bool {
+    fs::read_to_string(format!("/sys/block/{}/removable", disk))
+        .map(|value| value.trim() == "1")
+        .unwrap_or(false)
+}
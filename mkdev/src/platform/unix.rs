@@ -0,0 +1,174 @@
+// Other unix systems (macOS, the BSDs): an O_SYNC open and what stat says about the target,
+// without the ioctls, sysfs and mount tables the Linux backend relies on. Disks there are
+// block devices on macOS (/dev/diskN) and character devices on FreeBSD (/dev/da0), so
+// either counts as a device.
+
+use super::{Disk, Platform};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+pub struct Unix;
+
+impl Platform for Unix {
+    // The path of the device node
+    type Device = String;
+
+    const DISMOUNTS_ON_OPEN: bool = false;
+    const CHECKS_MOUNTS: bool = false;
+
+    // There is no O_DIRECT to ask for, so writes go through the cache with O_SYNC, or a
+    // plain open for targets that refuse it
+    fn open_target(&self, path: &str, readable: bool) -> io::Result<(File, bool)> {
+        let open = |flags: i32| {
+            OpenOptions::new()
+                .read(readable)
+                .write(true)
+                .custom_flags(flags)
+                .open(path)
+        };
+
+        if let Ok(file) = open(libc::O_SYNC) {
+            return Ok((file, false));
+        }
+        open(0).map(|file| (file, false))
+    }
+
+    // Direct I/O is never on, so there is only ever nothing to turn off
+    fn set_direct_io(&self, _file: &mut File, enabled: bool) -> io::Result<()> {
+        if enabled {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        Ok(())
+    }
+
+    fn block_device(&self, path: &Path) -> Option<String> {
+        let metadata = fs::metadata(path).ok()?;
+        is_device(&metadata).then(|| path.to_string_lossy().into_owned())
+    }
+
+    fn is_block_device(&self, file: &File) -> bool {
+        file.metadata().is_ok_and(|metadata| is_device(&metadata))
+    }
+
+    // Only the failed write says so
+    fn is_write_protected(&self, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    // Without a device tree to walk, the node given is all there is to show
+    fn disk_path(&self, device: &String) -> String {
+        device.clone()
+    }
+
+    // The mount table isn't read here
+    fn mounted_filesystems(&self, _device: &String) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    // Nothing to tell a stick from a system disk by, so nothing is refused for it (see
+    // CHECKS_MOUNTS)
+    fn is_removable(&self, _device: &String) -> bool {
+        true
+    }
+
+    fn is_partition(&self, _device: &String) -> bool {
+        false
+    }
+
+    // The same file, or a file on the filesystem the target device holds
+    fn overwrites(&self, target: &Path, source: &Path) -> bool {
+        let (Ok(target_meta), Ok(source_meta)) = (fs::metadata(target), fs::metadata(source))
+        else {
+            return false;
+        };
+        if target_meta.dev() == source_meta.dev() && target_meta.ino() == source_meta.ino() {
+            return true;
+        }
+        is_device(&target_meta)
+            && (target_meta.rdev() == source_meta.dev() || target_meta.rdev() == source_meta.rdev())
+    }
+
+    fn disks(&self) -> io::Result<Vec<Disk>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "listing disks is only supported on Linux and Windows",
+        ))
+    }
+
+    // Seeking to the end of a disk node lands at its size
+    fn device_size(&self, file: &File) -> io::Result<u64> {
+        let mut file = file;
+        let position = file.stream_position()?;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(position))?;
+        Ok(size)
+    }
+
+    fn logical_block_size(&self, _file: &File) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn io_sizes(&self, _file: &File) -> io::Result<(u64, u64)> {
+        Ok((0, 0))
+    }
+
+    fn file_capacity(&self, file: &File, _path: &Path) -> io::Result<u64> {
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatvfs(file.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+        Ok(file.metadata()?.len() + free)
+    }
+
+    fn discard(&self, _file: &File, _start: u64, _length: u64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    // Flushing is as close as it gets without fadvise
+    fn drop_written_pages(&self, file: &File, _start: u64, _end: u64) -> io::Result<()> {
+        file.sync_data()
+    }
+
+    fn drop_cache(&self, file: &File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    // Installed without SA_RESTART, as on Linux, so a blocked read sees the flag
+    fn set_interrupt_handler(&self, enabled: bool) {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = if enabled {
+            handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t
+        } else {
+            libc::SIG_DFL
+        };
+        unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+            libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+        }
+    }
+
+    fn is_transient(&self, error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(libc::EIO | libc::EAGAIN | libc::EBUSY | libc::ETIMEDOUT)
+        )
+    }
+
+    fn console(&self) -> io::Result<File> {
+        File::open("/dev/tty")
+    }
+}
+
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    crate::INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+fn is_device(metadata: &Metadata) -> bool {
+    metadata.file_type().is_block_device() || metadata.file_type().is_char_device()
+}
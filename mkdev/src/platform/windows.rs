@@ -0,0 +1,471 @@
+// Raw \\.\PhysicalDriveN handles for writing, DeviceIoControl for sizes, volumes and disk
+// properties. Windows won't let anyone write over a mounted volume, so every volume on the
+// target disk is locked and dismounted when it is opened.
+
+use super::{Disk, Platform};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use windows_sys::Win32::Foundation::{
+    BOOL, ERROR_BUSY, ERROR_IO_DEVICE, ERROR_SEM_TIMEOUT, ERROR_WRITE_PROTECT, FALSE, GENERIC_READ,
+    GENERIC_WRITE, INVALID_HANDLE_VALUE, TRUE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    GetDiskFreeSpaceExW, GetLogicalDrives, ReOpenFile, FILE_FLAG_NO_BUFFERING,
+    FILE_FLAG_WRITE_THROUGH, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+};
+use windows_sys::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+use windows_sys::Win32::System::Ioctl::{
+    PropertyStandardQuery, StorageDeviceProperty, DISK_EXTENT, DISK_GEOMETRY,
+    FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, GET_LENGTH_INFORMATION,
+    IOCTL_DISK_GET_DRIVE_GEOMETRY, IOCTL_DISK_GET_LENGTH_INFO, IOCTL_DISK_IS_WRITABLE,
+    IOCTL_STORAGE_QUERY_PROPERTY, STORAGE_DEVICE_DESCRIPTOR, STORAGE_PROPERTY_QUERY,
+};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+
+const PHYSICAL_DRIVE_PREFIX: &str = r"\\.\PhysicalDrive";
+
+// Disk numbers are assigned densely from 0 but can have gaps after a removal, so --list
+// probes this many rather than stopping at the first missing one
+const MAX_DISKS: u32 = 64;
+
+// Enough for a volume spanning several disks (dynamic disks, Storage Spaces)
+const MAX_EXTENTS: usize = 16;
+
+pub struct Windows;
+
+// VOLUME_DISK_EXTENTS with room for more than one extent
+#[repr(C)]
+struct DiskExtents {
+    count: u32,
+    extents: [DISK_EXTENT; MAX_EXTENTS],
+}
+
+impl Platform for Windows {
+    // The N of \\.\PhysicalDriveN
+    type Device = u32;
+
+    const DISMOUNTS_ON_OPEN: bool = true;
+    const CHECKS_MOUNTS: bool = true;
+
+    // FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH is O_DIRECT | O_SYNC. Disks are always
+    // opened readable, as IOCTL_DISK_GET_LENGTH_INFO needs read access.
+    fn open_target(&self, path: &str, readable: bool) -> io::Result<(File, bool)> {
+        let disk = physical_drive_number(path);
+        if let Some(disk) = disk {
+            dismount_volumes(disk)?;
+        }
+
+        let open = |flags: u32| {
+            OpenOptions::new()
+                .read(readable || disk.is_some())
+                .write(true)
+                .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+                .custom_flags(flags)
+                .open(path)
+        };
+
+        if let Ok(file) = open(FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH) {
+            return Ok((file, true));
+        }
+        if let Ok(file) = open(FILE_FLAG_WRITE_THROUGH) {
+            return Ok((file, false));
+        }
+        open(0).map(|file| (file, false))
+    }
+
+    // Flags can't be changed on an open handle, so reopen it with the new ones
+    fn set_direct_io(&self, file: &mut File, enabled: bool) -> io::Result<()> {
+        let flags = if enabled {
+            FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH
+        } else {
+            FILE_FLAG_WRITE_THROUGH
+        };
+        let position = file.stream_position()?;
+
+        // The new handle can't ask for more access than the old one had
+        let share = FILE_SHARE_READ | FILE_SHARE_WRITE;
+        let mut handle = unsafe {
+            ReOpenFile(
+                file.as_raw_handle(),
+                GENERIC_READ | GENERIC_WRITE,
+                share,
+                flags,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            handle = unsafe { ReOpenFile(file.as_raw_handle(), GENERIC_WRITE, share, flags) };
+        }
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        *file = unsafe { File::from_raw_handle(handle) };
+        file.seek(SeekFrom::Start(position))?;
+        Ok(())
+    }
+
+    fn block_device(&self, path: &Path) -> Option<u32> {
+        physical_drive_number(path.to_str()?)
+    }
+
+    // Regular files don't answer disk ioctls
+    fn is_block_device(&self, file: &File) -> bool {
+        self.device_size(file).is_ok()
+    }
+
+    // IOCTL_DISK_IS_WRITABLE fails with ERROR_WRITE_PROTECT for a locked card
+    fn is_write_protected(&self, path: &Path) -> io::Result<bool> {
+        let Some(disk) = self.block_device(path) else {
+            return Ok(false);
+        };
+        let file = open_device(&self.disk_path(&disk), false)?;
+        match ioctl(&file, IOCTL_DISK_IS_WRITABLE, None::<&()>, None::<&mut ()>) {
+            Ok(()) => Ok(false),
+            Err(e) if e.raw_os_error() == Some(ERROR_WRITE_PROTECT as i32) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn disk_path(&self, disk: &u32) -> String {
+        format!("{}{}", PHYSICAL_DRIVE_PREFIX, disk)
+    }
+
+    fn mounted_filesystems(&self, disk: &u32) -> Vec<(String, String)> {
+        volumes_on(*disk)
+            .into_iter()
+            .map(|letter| (format!(r"\\.\{}:", letter), format!(r"{}:\", letter)))
+            .collect()
+    }
+
+    fn is_removable(&self, disk: &u32) -> bool {
+        open_device(&self.disk_path(disk), false)
+            .and_then(|file| device_descriptor(&file))
+            .map(|(removable, _)| removable)
+            .unwrap_or(false)
+    }
+
+    // Only whole \\.\PhysicalDriveN disks are accepted as targets
+    fn is_partition(&self, _disk: &u32) -> bool {
+        false
+    }
+
+    // Files are compared by their canonical paths, and a file is on a disk when its drive
+    // letter is one of the disk's volumes
+    fn overwrites(&self, target: &Path, source: &Path) -> bool {
+        let Some(disk) = self.block_device(target) else {
+            return matches!(
+                (fs::canonicalize(target), fs::canonicalize(source)),
+                (Ok(target), Ok(source)) if target == source
+            );
+        };
+        if self.block_device(source) == Some(disk) {
+            return true;
+        }
+        // canonicalize gives verbatim paths such as \\?\D:\images\disk.img
+        let Ok(source) = fs::canonicalize(source) else {
+            return false;
+        };
+        let letter = source
+            .to_str()
+            .and_then(|path| path.strip_prefix(r"\\?\"))
+            .and_then(|path| path.chars().next())
+            .map(|letter| letter.to_ascii_uppercase());
+        letter.is_some_and(|letter| volumes_on(disk).contains(&letter))
+    }
+
+    fn disks(&self) -> io::Result<Vec<Disk>> {
+        let system = system_disks();
+        let system_drive = env::var("SystemDrive").unwrap_or_else(|_| "C:".to_string());
+
+        let mut disks = Vec::new();
+        for disk in 0..MAX_DISKS {
+            let path = self.disk_path(&disk);
+            let Ok(file) = open_device(&path, true) else {
+                continue;
+            };
+            let (removable, model) = device_descriptor(&file).unwrap_or_default();
+            disks.push(Disk {
+                size: self.device_size(&file).unwrap_or(0),
+                path,
+                model,
+                removable,
+                hosts: system
+                    .contains(&disk)
+                    .then(|| format!(r"{}\", system_drive)),
+            });
+        }
+        Ok(disks)
+    }
+
+    fn device_size(&self, file: &File) -> io::Result<u64> {
+        let mut info = GET_LENGTH_INFORMATION { Length: 0 };
+        ioctl(
+            file,
+            IOCTL_DISK_GET_LENGTH_INFO,
+            None::<&()>,
+            Some(&mut info),
+        )?;
+        Ok(info.Length as u64)
+    }
+
+    fn logical_block_size(&self, file: &File) -> io::Result<u64> {
+        let mut geometry: DISK_GEOMETRY = unsafe { mem::zeroed() };
+        ioctl(
+            file,
+            IOCTL_DISK_GET_DRIVE_GEOMETRY,
+            None::<&()>,
+            Some(&mut geometry),
+        )?;
+        Ok(geometry.BytesPerSector as u64)
+    }
+
+    // Windows has no notion of a preferred transfer size for a disk, only its sector size
+    fn io_sizes(&self, file: &File) -> io::Result<(u64, u64)> {
+        Ok((self.logical_block_size(file)?, 0))
+    }
+
+    fn file_capacity(&self, file: &File, path: &Path) -> io::Result<u64> {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut free: u64 = 0;
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide(directory.as_os_str()).as_ptr(),
+                &mut free,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if ok == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file.metadata()?.len() + free)
+    }
+
+    // Trimming a range takes IOCTL_STORAGE_MANAGE_DATA_SET_ATTRIBUTES and a device that
+    // reports support for it; zeroing works everywhere
+    fn discard(&self, _file: &File, _start: u64, _length: u64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    // There is no per-range eviction. Clean pages are the first the cache manager reclaims,
+    // so flushing is as close as it gets.
+    fn drop_written_pages(&self, file: &File, _start: u64, _end: u64) -> io::Result<()> {
+        file.sync_data()
+    }
+
+    // There is no call to evict a handle's cached pages. Writes with FILE_FLAG_NO_BUFFERING
+    // never entered the cache, and for the rest flushing is as close as it gets.
+    fn drop_cache(&self, file: &File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    // Removing the handler restores the default, which ends the process
+    fn set_interrupt_handler(&self, enabled: bool) {
+        let add = if enabled { TRUE } else { FALSE };
+        unsafe {
+            SetConsoleCtrlHandler(Some(handle_interrupt), add);
+        }
+    }
+
+    fn is_transient(&self, error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error().map(|code| code as u32),
+            Some(ERROR_IO_DEVICE | ERROR_BUSY | ERROR_SEM_TIMEOUT)
+        )
+    }
+
+    fn console(&self) -> io::Result<File> {
+        File::open("CONIN$")
+    }
+}
+
+unsafe extern "system" fn handle_interrupt(event: u32) -> BOOL {
+    match event {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            crate::INTERRUPTED.store(true, Ordering::Relaxed);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+// `\\.\PhysicalDrive2` -> 2, in any case
+fn physical_drive_number(path: &str) -> Option<u32> {
+    let prefix = path.get(..PHYSICAL_DRIVE_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(PHYSICAL_DRIVE_PREFIX) {
+        return None;
+    }
+    path[PHYSICAL_DRIVE_PREFIX.len()..].parse().ok()
+}
+
+// A handle good for ioctls; reading is only needed for the disk geometry ones
+fn open_device(path: &str, readable: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    if readable {
+        options.read(true);
+    } else {
+        options.access_mode(0);
+    }
+    options
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .open(path)
+}
+
+fn ioctl<I, O>(
+    file: &File,
+    code: u32,
+    input: Option<&I>,
+    output: Option<&mut O>,
+) -> io::Result<()> {
+    let (input, input_size) = match input {
+        Some(input) => ((input as *const I).cast(), mem::size_of::<I>() as u32),
+        None => (ptr::null(), 0),
+    };
+    let (output, output_size) = match output {
+        Some(output) => ((output as *mut O).cast(), mem::size_of::<O>() as u32),
+        None => (ptr::null_mut(), 0),
+    };
+    let mut returned = 0;
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            code,
+            input,
+            input_size,
+            output,
+            output_size,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Drive letters of the volumes that live, at least partly, on the disk
+fn volumes_on(disk: u32) -> Vec<char> {
+    let drives = unsafe { GetLogicalDrives() };
+    ('A'..='Z')
+        .enumerate()
+        .filter(|(bit, _)| drives & (1 << bit) != 0)
+        .map(|(_, letter)| letter)
+        .filter(|letter| volume_disks(&format!(r"\\.\{}:", letter)).contains(&disk))
+        .collect()
+}
+
+fn volume_disks(volume: &str) -> Vec<u32> {
+    let Ok(file) = open_device(volume, false) else {
+        return Vec::new();
+    };
+    let mut extents: DiskExtents = unsafe { mem::zeroed() };
+    if ioctl(
+        &file,
+        IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+        None::<&()>,
+        Some(&mut extents),
+    )
+    .is_err()
+    {
+        return Vec::new();
+    }
+    let count = (extents.count as usize).min(MAX_EXTENTS);
+    extents.extents[..count]
+        .iter()
+        .map(|extent| extent.DiskNumber)
+        .collect()
+}
+
+// The lock fails while anything has files open on the volume, which is what stops us from
+// writing under a running program. Locks last as long as their handle, so the handles are
+// kept open until mkdev exits.
+fn dismount_volumes(disk: u32) -> io::Result<()> {
+    for letter in volumes_on(disk) {
+        let volume = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+            .open(format!(r"\\.\{}:", letter))?;
+        ioctl(&volume, FSCTL_LOCK_VOLUME, None::<&()>, None::<&mut ()>).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot lock volume {}: {}", letter, e))
+        })?;
+        ioctl(&volume, FSCTL_DISMOUNT_VOLUME, None::<&()>, None::<&mut ()>).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot dismount volume {}: {}", letter, e),
+            )
+        })?;
+        mem::forget(volume);
+    }
+    Ok(())
+}
+
+// Disks holding the volume Windows runs from
+fn system_disks() -> Vec<u32> {
+    match env::var("SystemDrive") {
+        Ok(drive) => volume_disks(&format!(r"\\.\{}", drive)),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Whether the disk reports removable media, and its vendor and product names
+fn device_descriptor(file: &File) -> io::Result<(bool, String)> {
+    let query = STORAGE_PROPERTY_QUERY {
+        PropertyId: StorageDeviceProperty,
+        QueryType: PropertyStandardQuery,
+        AdditionalParameters: [0],
+    };
+    // The descriptor is followed by the strings its offsets point into
+    let mut buffer = [0u64; 128];
+    ioctl(
+        file,
+        IOCTL_STORAGE_QUERY_PROPERTY,
+        Some(&query),
+        Some(&mut buffer),
+    )?;
+
+    let descriptor: STORAGE_DEVICE_DESCRIPTOR = unsafe { ptr::read(buffer.as_ptr().cast()) };
+    let bytes: &[u8] =
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast(), mem::size_of_val(&buffer)) };
+    let string_at = |offset: u32| {
+        let start = offset as usize;
+        if start == 0 || start >= bytes.len() {
+            return String::new();
+        }
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(bytes.len(), |len| start + len);
+        String::from_utf8_lossy(&bytes[start..end])
+            .trim()
+            .to_string()
+    };
+
+    let model = [
+        string_at(descriptor.VendorIdOffset),
+        string_at(descriptor.ProductIdOffset),
+    ]
+    .into_iter()
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ");
+    Ok((descriptor.RemovableMedia != 0, model))
+}
+
+fn wide(text: &OsStr) -> Vec<u16> {
+    text.encode_wide().chain(Some(0)).collect()
+}
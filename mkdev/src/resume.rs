@@ -0,0 +1,117 @@
+// --resume: a small state file recording how much of the image is known to be on the target,
+// so a write that failed at 90% can carry on from there. One `key=value` per line, e.g.
+// source_size=4932407296
+// fingerprint=3f2a...
+// target=/dev/sdc
+// seek=0
+// skip=0
+// committed=4294967296
+// The offset is only recorded after the data up to it has been flushed to the device, so it
+// is never ahead of what a power cut would leave behind.
+
+use mkdev::{to_hex, SourceFile};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// How much of each end of the source goes into the fingerprint
+const FINGERPRINT_SPAN: u64 = 1024 * 1024;
+
+pub struct Checkpoint {
+    path: PathBuf,
+    source_size: u64,
+    fingerprint: String,
+    target: String,
+    seek: u64,
+    skip: u64,
+    // Where this run started, relative to the original --seek
+    resumed_at: u64,
+}
+
+impl Checkpoint {
+    // What a state file has to match for a run to pick it up. Hashing the whole source
+    // would take as long as a good part of the write, so its size and both ends stand in.
+    pub fn new(
+        path: &Path,
+        source: &mut SourceFile,
+        target: &str,
+        seek: u64,
+        skip: u64,
+    ) -> io::Result<Checkpoint> {
+        let source_size = source.size();
+        let mut hasher = Sha256::new();
+        for start in [0, source_size.saturating_sub(FINGERPRINT_SPAN)] {
+            source.seek(SeekFrom::Start(start))?;
+            io::copy(&mut (&mut *source).take(FINGERPRINT_SPAN), &mut hasher)?;
+        }
+        source.seek(SeekFrom::Start(0))?;
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            source_size,
+            fingerprint: to_hex(&hasher.finalize()),
+            target: target.to_string(),
+            seek,
+            skip,
+            resumed_at: 0,
+        })
+    }
+
+    // How far a previous run got, if the state file is there. An error if it was left by a
+    // write of another image, or to another place.
+    pub fn load(&mut self) -> Result<u64, String> {
+        let state = match fs::read_to_string(&self.path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.to_string()),
+        };
+        let value = |key: &str| {
+            state.lines().find_map(|line| {
+                let (name, value) = line.split_once('=')?;
+                (name.trim() == key).then(|| value.trim().to_string())
+            })
+        };
+        let number = |key: &str| value(key).and_then(|value| value.parse::<u64>().ok());
+        let committed = number("committed").ok_or("it has no committed offset")?;
+        if number("source_size") != Some(self.source_size)
+            || value("fingerprint").as_deref() != Some(self.fingerprint.as_str())
+        {
+            return Err("it was recorded for a different source image".to_string());
+        }
+        if value("target").as_deref() != Some(self.target.as_str())
+            || number("seek") != Some(self.seek)
+            || number("skip") != Some(self.skip)
+        {
+            return Err("it was recorded for another target, --seek or --skip".to_string());
+        }
+        self.resumed_at = committed;
+        Ok(committed)
+    }
+
+    // `written` bytes into this run are on the device. Written to a new file that replaces
+    // the old one, so a crash halfway leaves either the old offset or the new.
+    pub fn record(&self, written: u64) -> io::Result<()> {
+        let state = format!(
+            "source_size={}\nfingerprint={}\ntarget={}\nseek={}\nskip={}\ncommitted={}\n",
+            self.source_size,
+            self.fingerprint,
+            self.target,
+            self.seek,
+            self.skip,
+            self.resumed_at + written
+        );
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(state.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)
+    }
+
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// How mkdev and sig talk to a person, shared so both tools behave the same. `--quiet` leaves
+// only warnings and errors. `--plain` drops emoji and ANSI escapes, which garble logs, CI
+// output and dumb terminals; it is also on when NO_COLOR is set or stdout isn't a terminal.
+//
+// Messages for the user go through `say!` (stdout, silenced by --quiet) and `warn!` (stderr,
+// always shown). Output other programs read, such as --json or completion scripts, is
+// printed directly and never altered.
+
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+// Call once the command line is parsed; until then everything is shown as written
+pub fn init(quiet: bool, plain: bool) {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    QUIET.store(quiet, Ordering::Relaxed);
+    PLAIN.store(
+        plain || no_color || !io::stdout().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+// The text as it should be shown: in plain mode without emoji, or the spaces that followed
+// them, so "⚠️  Warning: ..." becomes "Warning: ..."
+pub fn display(text: &str) -> Cow<'_, str> {
+    if !plain() || !text.chars().any(is_emoji) {
+        return Cow::Borrowed(text);
+    }
+    let mut shown = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_emoji(c) {
+            shown.push(c);
+            continue;
+        }
+        while chars
+            .next_if(|&next| next == ' ' || is_emoji(next))
+            .is_some()
+        {}
+    }
+    Cow::Owned(shown)
+}
+
+// The text wrapped in an SGR escape, e.g. "1;32" for bold green, or as it is in plain mode
+pub fn color(text: &str, sgr: &str) -> String {
+    if plain() {
+        text.to_string()
+    } else {
+        format!("\x1b[{}m{}\x1b[0m", sgr, text)
+    }
+}
+
+// Pictographs and symbols as the tools use them, plus the variation selector and joiner
+// that turn plain symbols into emoji
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x23E9..=0x23FA | 0x2139
+            | 0xFE0F | 0x200D
+    )
+}
+
+// println! for messages the user can do without
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::quiet() {
+            println!("{}", $crate::display(&format!($($arg)*)));
+        }
+    };
+}
+
+// eprintln! for warnings and failures, shown even with --quiet
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        eprintln!("{}", $crate::display(&format!($($arg)*)))
+    };
+}
@@ -1,143 +1,1669 @@
+mod pattern;
+mod procs;
+mod report;
+mod signal;
+
+use args::completions::{self, Command, Complete, Shell};
+use args::Opt;
+use output::{say, warn};
+use pattern::Pattern;
+use procs::{ProcessTable, System};
+use report::{Outcome, Report};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::env;
-use std::fs;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often to check whether signalled processes have exited: often at first, for the ones
+// that exit straight away, backing off to the longest interval for the ones that don't
+const POLL_MIN: Duration = Duration::from_millis(10);
+const POLL_MAX: Duration = Duration::from_millis(100);
+
+// Signalling more name matches than this asks for confirmation first, unless --yes is given
+const CONFIRM_THRESHOLD: usize = 5;
+
+// Processes whose loss takes the machine, or the way into it, down with them. Signalling one
+// asks first, --yes or not, unless --force is given.
+const CRITICAL_PROCESSES: &[&str] = &["init", "systemd", "launchd", "sshd"];
+
+// How long --wait waits when no number of seconds is given
+const DEFAULT_WAIT: Duration = Duration::from_secs(10);
+
+// --interval without --repeat sends this many times, and --repeat without --interval this
+// far apart
+const DEFAULT_REPEAT: u32 = 3;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Which processes a name may match besides by the pattern itself
+struct Filter {
+    // Match the pattern against the whole command line rather than the names
+    full: bool,
+    include_self: bool,
+    user: Option<u32>,
+    older_than: Option<Duration>,
+    // Only processes whose controlling terminal is this device
+    tty: Option<u64>,
+    // Only the direct children of this process
+    parent: Option<i32>,
+}
+
+// Every option sig takes; see usage() for what they do
+const OPTIONS: &[Opt] = &[
+    Opt::flag("help", Some('h')),
+    Opt::flag("version", None),
+    Opt::value("generate-completions", None),
+    Opt::flag("list", Some('l')),
+    Opt::flag("doctor", None),
+    Opt::flag("check", None),
+    Opt::flag("count", None),
+    Opt::value("grace", None),
+    Opt::flag("all", Some('a')),
+    Opt::flag("exact", Some('e')),
+    Opt::flag("regex", None),
+    Opt::flag("full", Some('f')),
+    Opt::value("timeout", None),
+    Opt::optional_value("wait", None),
+    Opt::value("interval", None),
+    Opt::value("repeat", None),
+    Opt::flag("group", Some('g')),
+    Opt::flag("tree", Some('t')),
+    Opt::flag("session", None),
+    Opt::flag("parent", None),
+    Opt::optional_value("user", None),
+    Opt::value("older-than", None),
+    Opt::value("tty", None),
+    Opt::flag("by-memory", None),
+    Opt::flag("dry-run", Some('n')),
+    Opt::flag("verbose", Some('v')),
+    Opt::flag("json", None),
+    Opt::flag("quiet", Some('q')),
+    Opt::flag("plain", None),
+    Opt::flag("yes", Some('y')),
+    Opt::flag("force", None),
+    Opt::flag("include-self", None),
+    Opt::flag("stdin", None),
+];
+
+// The processes, or process groups, one target on the command line resolved to
+struct Batch<'a> {
+    target: &'a str,
+    by_name: bool,
+    pids: Vec<i32>,
+}
+
+// What to send and how, once the targets are known
+struct Delivery<'a> {
+    signal: i32,
+    signal_name: &'a str,
+    timeout: Option<Duration>,
+    wait: Option<Duration>,
+    dry_run: bool,
+    verbose: bool,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 3 {
-        eprintln!("Usage: sig <signal_type> [--all|-a] <process_id|process_name>");
-        eprintln!("Example: sig int 3626");
-        eprintln!("         sig 9 3626");
-        eprintln!("         sig kill chrome");
-        eprintln!("         sig kill --all chrome");
-        eprintln!("         sig kill -a chrome");
+    let parsed = match args::parse(OPTIONS, env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Run 'sig --help' for usage");
+            process::exit(1);
+        }
+    };
+
+    output::init(parsed.flag("quiet"), parsed.flag("plain"));
+
+    if parsed.flag("help") {
+        print!("{}", usage());
+        return;
+    }
+    if parsed.flag("version") {
+        println!("sig {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if let Some(shell) = parsed.value("generate-completions") {
+        print_completions(shell);
+        return;
+    }
+    if parsed.flag("list") {
+        if parsed.flag("json") {
+            signal::print_list_json();
+        } else {
+            signal::print_list();
+        }
+        return;
+    }
+    if parsed.flag("doctor") {
+        if !doctor() {
+            process::exit(1);
+        }
+        return;
+    }
+    // --tty is a selection of its own, so `sig --count --tty pts/3` needs no other target
+    let tty_name = parsed.value("tty");
+    let count = parsed.flag("count");
+    let signal_only = parsed.flag("check") || count;
+    let from_stdin = parsed.flag("stdin");
+    if parsed.positionals.is_empty() && !(signal_only && (tty_name.is_some() || from_stdin)) {
+        eprint!("{}", usage());
         process::exit(1);
     }
 
-    let signal_name = &args[1];
-    let mut all_flag = false;
-    let target: &String;
+    let mut report = Report::new(parsed.flag("json"));
+
+    if let Some(grace) = parsed.value("grace") {
+        if parsed.positionals.len() != 1 {
+            eprintln!("Error: Invalid arguments");
+            eprintln!("Usage: sig --grace <seconds> <process_id|process_name>");
+            process::exit(1);
+        }
+        let grace = match grace.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!(
+                    "Error: Invalid grace period '{}'. Use a number of seconds",
+                    grace
+                );
+                process::exit(1);
+            }
+        };
+        graceful_kill(
+            &mut report,
+            &parsed.positionals[0],
+            grace,
+            parsed.flag("force"),
+        );
+        report.finish();
+        return;
+    }
 
-    // Check for --all or -a flag
-    if args.len() == 4 && (args[2] == "--all" || args[2] == "-a") {
-        all_flag = true;
-        target = &args[3];
-    } else if args.len() == 3 {
-        target = &args[2];
+    // --check is signal 0: the existence and permission checks without any signal. --count
+    // sends nothing at all. Anything else takes the signal as its first positional argument.
+    let (signal_name, targets) = if signal_only {
+        ("0", &parsed.positionals[..])
     } else {
+        (parsed.positionals[0].as_str(), &parsed.positionals[1..])
+    };
+    // --stdin: the PIDs another tool picked, e.g. `pgrep foo | sig term --stdin`
+    let stdin_pids = if from_stdin {
+        if !targets.is_empty() {
+            eprintln!("Error: --stdin reads the targets from stdin; give none on the command line");
+            process::exit(1);
+        }
+        if parsed.flag("regex") {
+            eprintln!("Error: --stdin cannot be combined with --regex");
+            process::exit(1);
+        }
+        match read_stdin_pids() {
+            Ok(pids) if pids.is_empty() => {
+                eprintln!("Error: No process IDs on stdin");
+                process::exit(1);
+            }
+            Ok(pids) => pids,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let targets: Vec<&String> = targets.iter().chain(&stdin_pids).collect();
+    if targets.is_empty() && tty_name.is_none() {
         eprintln!("Error: Invalid arguments");
-        eprintln!("Usage: sig <signal_type> [--all|-a] <process_id|process_name>");
+        eprintln!("Usage: sig <signal_type> [options] <process_id|process_name>...");
         process::exit(1);
     }
 
-    // Try to parse signal as a number first, otherwise match by name
-    let signal = if let Ok(num) = signal_name.parse::<i32>() {
-        num
-    } else {
-        match signal_name.to_lowercase().as_str() {
-            "int" | "interrupt" | "sigint" => 2,
-            "term" | "terminate" | "sigterm" => 15,
-            "kill" | "sigkill" => 9,
-            "hup" | "hangup" | "sighup" => 1,
-            "quit" | "sigquit" => 3,
-            "usr1" | "sigusr1" => 10,
-            "usr2" | "sigusr2" => 12,
-            "stop" | "sigstop" => 19,
-            "cont" | "sigcont" => 18,
-            _ => {
-                eprintln!("Error: Unknown signal '{}'", signal_name);
-                eprintln!("Supported signals: int, term, kill, hup, quit, usr1, usr2, stop, cont");
-                eprintln!("Or use a signal number directly (e.g., 9 for SIGKILL)");
+    let all_flag = parsed.flag("all");
+    let exact = parsed.flag("exact");
+    let regex = parsed.flag("regex");
+    let full = parsed.flag("full");
+    let include_self = parsed.flag("include-self");
+    let dry_run = parsed.flag("dry-run");
+    let verbose = parsed.flag("verbose");
+    let assume_yes = parsed.flag("yes");
+    let force = parsed.flag("force");
+    let group = parsed.flag("group");
+    let tree = parsed.flag("tree");
+    let session = parsed.flag("session");
+    let parent = parsed.flag("parent");
+    let by_memory = parsed.flag("by-memory");
+
+    let user = match parsed.optional_value("user").map(resolve_user) {
+        None => None,
+        Some(Ok(uid)) => Some(uid),
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let wait = match parsed.optional_value("wait") {
+        None => None,
+        Some(None) => Some(DEFAULT_WAIT),
+        Some(Some(value)) => match value.parse::<u64>() {
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(_) => {
+                eprintln!("Error: Invalid wait '{}'. Use a number of seconds", value);
                 process::exit(1);
             }
+        },
+    };
+    let older_than = match parsed.value("older-than").map(parse_duration) {
+        None => None,
+        Some(Ok(age)) => Some(age),
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
     };
+    let tty = match tty_name.map(resolve_tty) {
+        None => None,
+        Some(Ok(dev)) => Some(dev),
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let timeout = match parsed.value("timeout") {
+        None => None,
+        Some(value) => match value.parse::<u64>() {
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(_) => {
+                eprintln!(
+                    "Error: Invalid timeout '{}'. Use a number of seconds",
+                    value
+                );
+                process::exit(1);
+            }
+        },
+    };
 
-    // Check if target is a PID or process name
-    if let Ok(pid) = target.parse::<i32>() {
-        // It's a PID
-        send_signal_to_pid(pid, signal, signal_name);
-    } else {
-        // It's a process name
-        let pids = find_processes_by_name(target);
-
-        if pids.is_empty() {
-            eprintln!("Error: No processes found with name '{}'", target);
+    let interval = match parsed.value("interval").map(parse_duration) {
+        None => None,
+        Some(Ok(interval)) if !interval.is_zero() => Some(interval),
+        Some(Ok(_)) => {
+            eprintln!("Error: --interval needs a time greater than 0");
             process::exit(1);
         }
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let repeat = match parsed.value("repeat") {
+        None => None,
+        Some(value) => match value.parse::<u32>() {
+            Ok(times) if times > 0 => Some(times),
+            _ => {
+                eprintln!(
+                    "Error: Invalid repeat count '{}'. Use a number of times (e.g. 3)",
+                    value
+                );
+                process::exit(1);
+            }
+        },
+    };
+    // Either option turns repeating on, with the default for the other
+    let rounds = match (interval, repeat) {
+        (None, None) => None,
+        (interval, repeat) => Some((
+            interval.unwrap_or(DEFAULT_INTERVAL),
+            repeat.unwrap_or(DEFAULT_REPEAT),
+        )),
+    };
+    if rounds.is_some() && (tree || dry_run || signal_only) {
+        eprintln!("Error: --interval and --repeat cannot be combined with --tree, --dry-run,");
+        eprintln!("--check or --count");
+        process::exit(1);
+    }
+
+    if exact && regex {
+        eprintln!("Error: --exact and --regex cannot be combined");
+        process::exit(1);
+    }
+    if group && tree {
+        eprintln!("Error: --group and --tree cannot be combined");
+        process::exit(1);
+    }
+    if session && (group || tree || parent) {
+        eprintln!("Error: --session cannot be combined with --group, --tree or --parent");
+        process::exit(1);
+    }
+    if by_memory && tree {
+        eprintln!(
+            "Error: --by-memory cannot be combined with --tree, which signals children first"
+        );
+        process::exit(1);
+    }
+
+    // A PID is a PID, unless it's meant as a regex. Compile every pattern up front so a bad
+    // one fails before anything is signalled. With --parent every target is a PID whose
+    // children are matched, whatever their names.
+    let mut queries = Vec::with_capacity(targets.len());
+    for target in targets {
+        let target = target.as_str();
+        if parent {
+            match target.parse::<i32>() {
+                Ok(ppid) if ppid > 0 => {
+                    queries.push((target, Some(ppid), Pattern::Substring(String::new())));
+                    continue;
+                }
+                _ => {
+                    eprintln!("Error: --parent takes process IDs, not '{}'", target);
+                    process::exit(1);
+                }
+            }
+        }
+        let pid = target.parse::<i32>().ok().filter(|_| !regex);
+        match Pattern::new(target, exact, regex) {
+            Ok(pattern) => queries.push((target, pid, pattern)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    // --tty without a name takes every process on the terminal
+    let whole_tty = queries.is_empty();
+    if let Some(name) = tty_name.filter(|_| whole_tty) {
+        queries.push((name, None, Pattern::Substring(String::new())));
+    }
 
-        if pids.len() > 1 && !all_flag {
-            println!("Found {} processes with name '{}':", pids.len(), target);
-            for pid in &pids {
-                println!("  PID: {}", pid);
+    // Try to parse signal as a number first, otherwise match by name
+    let signal = match signal::parse(signal_name) {
+        Ok(Some(num)) => num,
+        Ok(None) => {
+            eprintln!("Error: Unknown signal '{}'", signal_name);
+            eprintln!("Supported signals: {}", signal::short_names().join(", "));
+            if signal::rt_range().is_some() {
+                eprintln!("Real-time signals: rtmin, rtmin+N, rtmax, rtmax-N");
             }
-            eprintln!("\nUse --all or -a flag to send signal to all processes");
+            eprintln!("Or use a signal number directly (e.g., 9 for SIGKILL)");
+            eprintln!("Run 'sig --list' to see them all");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
+    };
+
+    let filter = Filter {
+        full,
+        include_self,
+        user,
+        older_than,
+        tty,
+        parent: None,
+    };
+    let delivery = Delivery {
+        signal,
+        signal_name,
+        timeout,
+        wait,
+        dry_run,
+        verbose,
+    };
+
+    // Resolve every target before signalling anything, so that an ambiguous name stops the
+    // whole run. Process groups are signalled as negative PIDs, the way kill(2) takes them.
+    let mut batches: Vec<Batch> = Vec::new();
+    for (target, pid, pattern) in &queries {
+        let pids = if let Some(pid) = pid.filter(|_| session) {
+            let groups = session_groups(&[pid], include_self);
+            if groups.is_empty() {
+                let own_session = System.session(process::id() as i32);
+                if count {
+                    continue;
+                }
+                if own_session.is_some() && System.session(pid) == own_session {
+                    eprintln!("Error: Process {} is in sig's own session", pid);
+                    eprintln!("Use --include-self to signal it anyway");
+                } else {
+                    eprintln!("Error: No session found for process {}", pid);
+                }
+                continue;
+            }
+            groups
+        } else if let Some(pid) = pid.filter(|_| !parent) {
+            let pid = if group { -pid } else { pid };
+            if count && !process_exists(pid) {
+                continue;
+            }
+            if dry_run && !tree && !process_exists(pid) {
+                eprintln!("Error: No {} found", describe(pid));
+                report.exit(1);
+            }
+            vec![pid]
+        } else {
+            // Under --parent the PID is the parent to match; otherwise there is none
+            let filter = Filter {
+                parent: *pid,
+                ..filter
+            };
+            let Matches { pids, unreadable } = find_processes_by_name(pattern, &filter);
+            if pids.is_empty() {
+                if parent && !count {
+                    eprintln!("Error: No child processes found for process {}", target);
+                } else if whole_tty && !count {
+                    eprintln!("Error: No processes found on {}", target);
+                } else if !count {
+                    eprintln!("Error: No processes found with name '{}'", target);
+                    hint_unreadable(unreadable);
+                }
+                continue;
+            }
+
+            // Checking touches nothing, so any number of matches is fine, and a whole
+            // terminal or a parent's children are meant to be more than one process
+            if pids.len() > 1 && !all_flag && signal != 0 && !whole_tty && !parent {
+                report.note(&format!(
+                    "Found {} processes with name '{}':",
+                    pids.len(),
+                    target
+                ));
+                print_processes(&report, &pids, false);
+                eprintln!("\nUse --all or -a flag to send signal to all processes");
+                report.exit(1);
+            }
 
-        // Send signal to all found processes
-        let mut success_count = 0;
-        for pid in &pids {
-            if send_signal_to_pid(*pid, signal, signal_name) {
-                success_count += 1;
+            if group {
+                let groups = process_groups(pids, include_self);
+                if groups.is_empty() {
+                    if !count {
+                        eprintln!("Error: No process groups found for '{}'", target);
+                    }
+                    continue;
+                }
+                groups
+            } else if session {
+                let groups = session_groups(&pids, include_self);
+                if groups.is_empty() {
+                    if !count {
+                        eprintln!("Error: No sessions found for '{}'", target);
+                    }
+                    continue;
+                }
+                groups
+            } else {
+                pids
             }
+        };
+
+        // A process matched by more than one target is only signalled once
+        let mut pids: Vec<i32> = pids
+            .into_iter()
+            .filter(|pid| !batches.iter().any(|batch| batch.pids.contains(pid)))
+            .collect();
+        if by_memory {
+            sort_by_memory(&mut pids);
         }
+        batches.push(Batch {
+            target,
+            by_name: pid.is_none() || parent || session,
+            pids,
+        });
+    }
+    // PIDs from stdin are one target. Another tool chose them, much as a name would, so they
+    // get the same confirmation and summary as matches.
+    if from_stdin {
+        let mut pids: Vec<i32> = batches.drain(..).flat_map(|batch| batch.pids).collect();
+        if by_memory {
+            sort_by_memory(&mut pids);
+        }
+        batches.push(Batch {
+            target: "stdin",
+            by_name: true,
+            pids,
+        });
+    }
+    // Targets that matched nothing have been reported; go on as long as any did
+    batches.retain(|batch| !batch.pids.is_empty());
+    if count {
+        print_count(&batches, tree, include_self);
+        return;
+    }
+    if batches.is_empty() {
+        report.exit(1);
+    }
+
+    if signal != 0 && !dry_run && !force {
+        let pids: Vec<i32> = batches
+            .iter()
+            .flat_map(|batch| batch.pids.iter().copied())
+            .collect();
+        guard_critical(&report, &pids, signal_name);
+    }
+
+    let matched: Vec<i32> = batches
+        .iter()
+        .filter(|batch| batch.by_name)
+        .flat_map(|batch| batch.pids.iter().copied())
+        .collect();
+    if matched.len() > CONFIRM_THRESHOLD && signal != 0 && !dry_run && !assume_yes {
+        confirm(&report, &matched, signal_name);
+    }
 
-        if success_count > 0 {
-            println!("Successfully sent signal to {} process(es)", success_count);
+    if tree {
+        let roots: Vec<i32> = batches
+            .iter()
+            .flat_map(|batch| batch.pids.iter().copied())
+            .collect();
+        signal_tree(&mut report, &roots, &delivery, include_self);
+        report.finish();
+        return;
+    }
+
+    // With several targets, each one's results go under its name
+    let headed = batches.len() > 1;
+
+    if dry_run {
+        for batch in &batches {
+            if headed {
+                report.note(&format!("{}:", batch.target));
+            }
+            report_dry_run(&mut report, &batch.pids, &delivery);
         }
+        report.finish();
+        return;
     }
-}
 
-fn send_signal_to_pid(pid: i32, signal: i32, signal_name: &str) -> bool {
-    unsafe {
-        let result = libc::kill(pid, signal);
-        if result == 0 {
-            println!("Signal {} sent to process {}", signal_name, pid);
-            true
-        } else {
-            let errno = *libc::__errno_location();
-            match errno {
-                libc::ESRCH => eprintln!("Error: No process with PID {} found", pid),
-                libc::EPERM => {
-                    eprintln!("Error: Permission denied to send signal to process {}", pid)
+    let mut signalled = Vec::new();
+    let mut none_alive = false;
+    for batch in &batches {
+        if headed {
+            report.note(&format!("{}:", batch.target));
+        }
+        if verbose {
+            announce(&report, &batch.pids);
+        }
+        let sent: Vec<i32> = batch
+            .pids
+            .iter()
+            .copied()
+            .filter(|&pid| deliver(&mut report, pid, signal, signal_name))
+            .collect();
+
+        if signal == 0 {
+            none_alive |= sent.is_empty();
+        } else if batch.by_name && !sent.is_empty() {
+            let kind = if group || session {
+                "process group(s)"
+            } else {
+                "process(es)"
+            };
+            report.note(&format!(
+                "Successfully sent signal to {} {}",
+                sent.len(),
+                kind
+            ));
+        }
+        signalled.extend(sent);
+    }
+
+    // --interval/--repeat: send the signal again to whatever is still running, until the
+    // rounds run out or it has all gone. Names taken with --all, a whole terminal and
+    // --parent are looked up afresh each time, so that new matches get it too.
+    if let Some((interval, repeat)) = rounds {
+        for round in 2..=repeat {
+            thread::sleep(interval);
+            let mut pids: Vec<i32> = signalled
+                .iter()
+                .copied()
+                .filter(|&pid| process_exists(pid))
+                .collect();
+            for (_, pid, pattern) in &queries {
+                if !parent && (pid.is_some() || !(all_flag || whole_tty)) {
+                    continue;
+                }
+                let filter = Filter {
+                    parent: *pid,
+                    ..filter
+                };
+                let matched = find_processes_by_name(pattern, &filter).pids;
+                let matched = if group {
+                    process_groups(matched, include_self)
+                } else if session {
+                    session_groups(&matched, include_self)
+                } else {
+                    matched
+                };
+                for pid in matched {
+                    if !pids.contains(&pid) {
+                        pids.push(pid);
+                    }
+                }
+            }
+            if pids.is_empty() {
+                report.note("All targets have exited");
+                break;
+            }
+            report.note(&format!(
+                "Round {} of {}: sending {} to {} process(es)",
+                round,
+                repeat,
+                signal_name,
+                pids.len()
+            ));
+            for pid in pids {
+                if deliver(&mut report, pid, signal, signal_name) && !signalled.contains(&pid) {
+                    signalled.push(pid);
                 }
-                libc::EINVAL => eprintln!("Error: Invalid signal number"),
-                _ => eprintln!(
-                    "Error: Failed to send signal to PID {} (errno: {})",
-                    pid, errno
-                ),
             }
-            false
         }
     }
+
+    // --check fails when any target has nothing left running
+    if none_alive {
+        report.exit(1);
+    }
+    settle(&mut report, &signalled, &delivery);
+
+    report.finish();
+}
+
+// --count: just the number of processes (or groups) that would be signalled, for scripts
+fn print_count(batches: &[Batch], tree: bool, include_self: bool) {
+    let pids: Vec<i32> = batches
+        .iter()
+        .flat_map(|batch| batch.pids.iter().copied())
+        .collect();
+    let count = if tree {
+        let excluded = if include_self {
+            Vec::new()
+        } else {
+            own_process_chain()
+        };
+        process_tree(&pids)
+            .into_iter()
+            .filter(|pid| !excluded.contains(pid))
+            .count()
+    } else {
+        pids.len()
+    };
+    println!("{}", count);
 }
 
-fn find_processes_by_name(name: &str) -> Vec<i32> {
+// Whitespace-separated PIDs, as pgrep and pidof print them. Anything else is an error
+// rather than a name to match, since a stray word would otherwise signal whatever it names.
+fn read_stdin_pids() -> Result<Vec<String>, String> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("Cannot read stdin: {}", e))?;
     let mut pids = Vec::new();
+    for word in input.split_whitespace() {
+        match word.parse::<i32>() {
+            Ok(pid) if pid > 0 => pids.push(word.to_string()),
+            _ => return Err(format!("--stdin takes process IDs, not '{}'", word)),
+        }
+    }
+    Ok(pids)
+}
 
-    if let Ok(entries) = fs::read_dir("/proc") {
-        for entry in entries.flatten() {
-            if let Ok(file_name) = entry.file_name().into_string() {
-                if let Ok(pid) = file_name.parse::<i32>() {
-                    // Read the cmdline file to get the process name
-                    let cmdline_path = format!("/proc/{}/cmdline", pid);
-                    if let Ok(cmdline) = fs::read_to_string(&cmdline_path) {
-                        // cmdline uses null bytes as separators
-                        let cmd = cmdline.split('\0').next().unwrap_or("");
-
-                        // Extract just the executable name
-                        let exe_name = cmd.split('/').last().unwrap_or(cmd);
-
-                        if exe_name.contains(name) || cmd.contains(name) {
-                            pids.push(pid);
-                        }
-                    }
+// Printed to stdout for --help, and to stderr when there is nothing to do
+fn usage() -> String {
+    format!(
+        "\
+Usage: sig <signal_type> [options] <process_id|process_name>...
+       sig --grace <seconds> <process_id|process_name>
+       sig --check [options] <process_id|process_name>...
+       sig --count [options] <process_id|process_name>...
+       sig <signal_type> --tty <terminal> [options] [process_name]...
+       sig --list [--json]
+       sig --doctor
+Signals: {signals}, or any number
+         (sig --list shows them all, with their numbers)
+Options may come anywhere on the command line, before or after the signal.
+Options:
+  --all, -a            Signal every matching process
+  --exact, -e          Match the process name exactly. Without it names match
+                       by substring, so 'ssh' also matches sshd and ssh-agent
+  --regex              Match names against a POSIX extended regex
+                       ('chrome*' style globs with * and ? work without it)
+  --full, -f           Match against the whole command line, arguments and all
+  --timeout <seconds>  SIGKILL any process still running after the timeout
+  --wait [seconds]     Wait for the processes to exit (default: 10s), and fail
+                       if any are still running after that
+  --repeat <n>         Send the signal n times (default: 3 with --interval) to
+                       whatever is still running, stopping once it has all gone
+  --interval <time>    Wait this long between those sends (default: 1s); in
+                       seconds or with an s, m, h or d suffix
+  --group, -g          Signal the whole process group (a PID is taken as a PGID)
+  --tree, -t           Also signal every descendant, children before parents
+  --session            Signal every process group in the target's session: the
+                       terminal's shell and every job started from it
+  --parent             Take the targets as parent PIDs and signal their direct
+                       children instead
+  --user [name|uid]    Only match processes owned by this user (default: you)
+  --older-than <age>   Only match processes started longer ago than this,
+                       in seconds or with an s, m, h or d suffix (e.g. 2h)
+  --tty <terminal>     Only match processes on this terminal (pts/3, tty1 or a
+                       /dev path); with no names, every process on it
+  --by-memory          Signal the matches using the most memory first; --verbose
+                       shows each one's resident size
+  --dry-run, -n        Show which processes would be signalled, and stop
+  --count              Print how many processes match, and stop without signalling
+  --verbose, -v        List the command line of each process before signalling
+  --json               Print the result for each process as a JSON array
+  --quiet, -q          Only print errors; the exit status tells the rest
+  --plain              Plain text output (also set by NO_COLOR, or when stdout is not
+                       a terminal)
+  --yes, -y            Don't ask before signalling more than {threshold} processes by name
+  --force              Allow signalling PID 1 and the names in
+                       ~/.config/splash/sig-protected, and don't ask before
+                       signalling init, systemd, launchd or sshd
+  --include-self       Let a name match sig itself and the shells it runs under
+  --stdin              Read the target PIDs from stdin, separated by spaces or
+                       newlines, instead of the command line
+  --list, -l           List the supported signals and exit; with --json, as a JSON
+                       array with each one's aliases, default action and whether
+                       it can be caught
+  --doctor             Check that sig can read the process table here, and exit
+  --help, -h           Show this help and exit
+  --generate-completions <bash|zsh|fish>
+                       Print a completion script for the shell and exit
+  --version            Show the version and exit
+Example: sig int 3626
+         sig 9 3626
+         sig kill chrome
+         sig kill --all chrome
+         sig term nginx redis memcached
+         sig kill -a chrome
+         sig kill --exact ssh
+         sig kill -a 'chrome*'
+         sig term --regex '^node$'
+         sig term -f 'python manage.py runserver'
+         sig term --timeout 5 chrome
+         sig term --wait 4242
+         sig term --interval 1 --repeat 3 stuck
+         sig kill --user alice -a python
+         sig kill --older-than 2h -a firefox
+         sig hup --tty pts/3
+         sig kill -a -v --by-memory chrome
+         sig term --tree 4242
+         sig hup --session 4321
+         sig term --parent 4242
+         pgrep -f worker | sig term --stdin
+         sig --check 4242
+         sig --count firefox
+         sig --json kill -a nginx
+         sig --grace 5 chrome
+
+Exit status: 0 if every signal was sent, otherwise the number of processes that could
+not be signalled (at most 125). 1 if nothing matched, or --check or --wait failed.
+",
+        threshold = CONFIRM_THRESHOLD,
+        signals = signal::short_names().join(", ")
+    )
+}
+
+// --generate-completions: signal names for the signal, process names for the targets
+fn print_completions(shell: &str) {
+    let Some(shell) = Shell::from_name(shell) else {
+        eprintln!("Error: Unknown shell '{}'. Use bash, zsh or fish", shell);
+        process::exit(1);
+    };
+    // Every name a signal goes by, short ones first, as typed on the command line
+    let mut signal_names: Vec<String> = signal::SIGNALS
+        .iter()
+        .flat_map(|def| def.aliases().iter().map(|name| name.to_string()))
+        .collect();
+    signal_names.extend(signal::SIGNALS.iter().map(|def| def.name().to_lowercase()));
+    signal_names.extend(["rtmin".to_string(), "rtmax".to_string()]);
+    let command = Command {
+        name: "sig",
+        options: OPTIONS,
+        values: vec![
+            (
+                "generate-completions",
+                Complete::Words(vec!["bash".into(), "zsh".into(), "fish".into()]),
+            ),
+            ("user", Complete::Users),
+            (
+                "tty",
+                Complete::Command("ps -eo tty= | grep -v '^?' | sort -u"),
+            ),
+        ],
+        positionals: vec![
+            Complete::Words(signal_names),
+            Complete::Command("ps -eo comm="),
+        ],
+    };
+    print!("{}", completions::generate(shell, &command));
+}
+
+// --doctor: whether sig can see processes here at all. Without /proc, as in some
+// containers, every name simply matches nothing, which looks like no such process.
+fn doctor() -> bool {
+    match System.probe() {
+        Ok(source) => say!("✅ Reading processes from {}", source),
+        Err(e) => {
+            warn!("❌ {}", e);
+            return false;
+        }
+    }
+    let pids = System.pids();
+    if pids.is_empty() {
+        warn!("❌ No processes are visible");
+        return false;
+    }
+
+    let mut healthy = true;
+    let own = process::id() as i32;
+    if !pids.contains(&own) {
+        warn!(
+            "❌ sig itself (PID {}) is not among the {} visible processes",
+            own,
+            pids.len()
+        );
+        healthy = false;
+    }
+    let args = System.args(own);
+    match System.comm(own) {
+        Some(comm) if !args.is_empty() => {
+            say!(
+                "✅ Own process {} reads as '{}': {}",
+                own,
+                comm,
+                args.join(" ")
+            )
+        }
+        Some(_) => {
+            warn!(
+                "❌ The command line of sig's own process {} can't be read",
+                own
+            );
+            healthy = false;
+        }
+        None => {
+            warn!("❌ The name of sig's own process {} can't be read", own);
+            healthy = false;
+        }
+    }
+
+    // The per-process fields are where parsing goes wrong, so check them against what the
+    // kernel says directly
+    let (ppid, uid) = unsafe { (libc::getppid(), libc::getuid()) };
+    let (read_ppid, read_uid) = (System.parent(own), System.uid(own));
+    if read_ppid == Some(ppid) && read_uid == Some(uid) {
+        say!("✅ Parent PID {} and UID {} read back correctly", ppid, uid);
+    } else {
+        warn!(
+            "❌ Read parent PID {:?} and UID {:?} for sig, but getppid() is {} and getuid() is {}",
+            read_ppid, read_uid, ppid, uid
+        );
+        healthy = false;
+    }
+
+    let named = pids
+        .iter()
+        .filter(|&&pid| System.comm(pid).is_some())
+        .count();
+    say!(
+        "✅ {} processes visible, {} with a readable name",
+        pids.len(),
+        named
+    );
+    if uid != 0
+        && pids
+            .iter()
+            .all(|&pid| System.uid(pid).is_none_or(|owner| owner == uid))
+    {
+        warn!("⚠️  Only your own processes are visible (is /proc mounted with hidepid?)");
+    }
+    healthy
+}
+
+// --dry-run: everything up to the point of sending
+fn report_dry_run(report: &mut Report, pids: &[i32], delivery: &Delivery) {
+    if report.is_json() {
+        for &pid in pids {
+            report.record(pid, &command_line(pid.abs()), delivery.signal, "dry_run");
+        }
+        return;
+    }
+    say!(
+        "Would send signal {} to {} {}:",
+        delivery.signal_name,
+        pids.len(),
+        if pids.iter().any(|&pid| pid < 0) {
+            "process group(s)"
+        } else {
+            "process(es)"
+        }
+    );
+    print_processes(report, pids, delivery.verbose);
+    if let Some(timeout) = delivery.timeout {
+        say!(
+            "Then SIGKILL any still running after {}s",
+            timeout.as_secs()
+        );
+    }
+    if let Some(wait) = delivery.wait {
+        say!("Then wait up to {}s for them to exit", wait.as_secs());
+    }
+}
+
+// Ask on the terminal before signalling a large number of matches. Everything goes to
+// stderr so that --json output stays clean.
+fn confirm(report: &Report, pids: &[i32], signal_name: &str) {
+    eprintln!(
+        "About to send signal {} to {} processes:",
+        signal_name,
+        pids.len()
+    );
+    for &pid in pids {
+        if pid < 0 {
+            eprintln!("  PGID: {} ({})", -pid, command_line(-pid));
+        } else {
+            eprintln!("  PID: {} ({})", pid, command_line(pid));
+        }
+    }
+
+    ask(report, "--yes");
+}
+
+// Ask on the terminal itself, since stdin may be redirected, and exit unless the answer is
+// yes. `skip` is the option that goes without asking.
+fn ask(report: &Report, skip: &str) {
+    let tty = match File::open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => {
+            eprintln!("Error: No terminal to ask for confirmation on.");
+            eprintln!("Use {} to signal them without confirmation.", skip);
+            report.exit(1);
+        }
+    };
+
+    eprint!("Are you sure you want to continue? (yes/no): ");
+
+    let mut confirmation = String::new();
+    BufReader::new(tty)
+        .read_line(&mut confirmation)
+        .expect("Failed to read input");
+
+    if confirmation.trim().to_lowercase() != "yes" {
+        eprintln!("Operation cancelled.");
+        report.exit(1);
+    }
+}
+
+// PID 1 is refused outright: killing init panics the kernel, and as a process group 1
+// (-1) means every process there is. So are the names in the user's protected list.
+// Anything else in CRITICAL_PROCESSES is confirmed first. All of it is skipped with --force.
+fn guard_critical(report: &Report, pids: &[i32], signal_name: &str) {
+    if let Some(&pid) = pids.iter().find(|pid| pid.abs() == 1) {
+        eprintln!(
+            "Error: Refusing to send {} to {} ({})",
+            signal_name,
+            describe(pid),
+            command_line(1)
+        );
+        eprintln!("Use --force to signal it anyway.");
+        report.exit(1);
+    }
+
+    // A group is as protected as its leader
+    if let Some((path, protected)) = protected_names() {
+        let refused: Vec<i32> = pids
+            .iter()
+            .copied()
+            .filter(|&pid| {
+                process_names(pid.abs())
+                    .iter()
+                    .any(|name| protected.contains(name))
+            })
+            .collect();
+        if !refused.is_empty() {
+            eprintln!(
+                "Error: Refusing to send {} to processes protected by {}:",
+                signal_name,
+                path.display()
+            );
+            for &pid in &refused {
+                eprintln!("  {} ({})", describe(pid), command_line(pid.abs()));
+            }
+            eprintln!("Use --force to signal them anyway.");
+            report.exit(1);
+        }
+    }
+
+    let critical: Vec<i32> = pids
+        .iter()
+        .copied()
+        .filter(|&pid| is_critical(pid.abs()))
+        .collect();
+    if critical.is_empty() {
+        return;
+    }
+    warn!(
+        "⚠️  About to send {} to critical system processes:",
+        signal_name
+    );
+    for &pid in &critical {
+        warn!("  {} ({})", describe(pid), command_line(pid.abs()));
+    }
+    ask(report, "--force");
+}
+
+// The protected list: one process name per line, matched like --exact, with blank lines
+// and # comments skipped. $XDG_CONFIG_HOME/splash/sig-protected, which is usually
+// ~/.config/splash/sig-protected. None when there is no such file.
+fn protected_names() -> Option<(PathBuf, Vec<String>)> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    let path = config.join("splash").join("sig-protected");
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("⚠️  Can't read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let names = contents
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(name, _)| name).trim())
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+    Some((path, names))
+}
+
+fn is_critical(pid: i32) -> bool {
+    process_names(pid)
+        .iter()
+        .any(|name| CRITICAL_PROCESSES.contains(&name.as_str()))
+}
+
+// --verbose: what is about to be signalled, read before anything can exit. The JSON
+// output carries the command lines already.
+fn announce(report: &Report, pids: &[i32]) {
+    report.note("Signalling:");
+    print_processes(report, pids, true);
+}
+
+// Groups are listed by their leader's command line, and with `memory` its resident size
+fn print_processes(report: &Report, pids: &[i32], memory: bool) {
+    for &pid in pids {
+        let rss = System
+            .rss(pid.abs())
+            .filter(|_| memory)
+            .map(|bytes| format!(", RSS {}", format_memory(bytes)))
+            .unwrap_or_default();
+        if pid < 0 {
+            report.note(&format!("  PGID: {}{} ({})", -pid, rss, command_line(-pid)));
+        } else {
+            report.note(&format!("  PID: {}{} ({})", pid, rss, command_line(pid)));
+        }
+    }
+}
+
+// --by-memory: the largest resident size first, groups by their leader's. Processes whose
+// size can't be read (gone, or kernel threads) go last.
+fn sort_by_memory(pids: &mut [i32]) {
+    pids.sort_by_cached_key(|pid| Reverse(System.rss(pid.abs())));
+}
+
+fn format_memory(bytes: u64) -> String {
+    let mb = bytes as f64 / 1_000_000.0;
+    if mb >= 1000.0 {
+        format!("{:.1} GB", mb / 1000.0)
+    } else {
+        format!("{:.1} MB", mb)
+    }
+}
+
+// "process 42", or "process group 42" for a negative PID
+fn describe(pid: i32) -> String {
+    if pid < 0 {
+        format!("process group {}", -pid)
+    } else {
+        format!("process {}", pid)
+    }
+}
+
+// The arguments joined with spaces, or the bracketed comm for kernel threads, like ps shows
+fn command_line(pid: i32) -> String {
+    if let Some(cmdline) = full_command_line(pid) {
+        return cmdline;
+    }
+    match System.comm(pid) {
+        Some(comm) => format!("[{}]", comm),
+        None => "?".to_string(),
+    }
+}
+
+fn send_signal_to_pid(pid: i32, signal: i32) -> Outcome {
+    // Take errno straight after the call, before anything else can overwrite it
+    let result = match unsafe { libc::kill(pid, signal) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    };
+    let zombie = signal == 0 && System.is_zombie(pid);
+    match result.map_err(|e| e.raw_os_error()) {
+        Ok(()) | Err(Some(libc::EPERM)) if zombie => Outcome::Zombie,
+        Ok(()) => Outcome::Sent,
+        Err(Some(libc::ESRCH)) => Outcome::NoSuchProcess,
+        Err(Some(libc::EPERM)) => Outcome::PermissionDenied,
+        Err(Some(libc::EINVAL)) => Outcome::InvalidSignal,
+        Err(errno) => Outcome::Failed(errno.unwrap_or(0)),
+    }
+}
+
+// Matches from the same job share a group, which must only be signalled once. The groups
+// sig and its shells run in are as off limits as the processes themselves.
+fn process_groups(pids: Vec<i32>, include_self: bool) -> Vec<i32> {
+    let own_groups: Vec<i32> = if include_self {
+        Vec::new()
+    } else {
+        own_process_chain()
+            .into_iter()
+            .filter_map(|pid| System.group(pid))
+            .collect()
+    };
+    let mut groups = Vec::new();
+    for pid in pids {
+        match System.group(pid) {
+            Some(pgid) if own_groups.contains(&pgid) => {}
+            Some(pgid) if !groups.contains(&-pgid) => groups.push(-pgid),
+            _ => {}
+        }
+    }
+    groups
+}
+
+// --session: every process group in the sessions of the given processes, each session
+// leader's own group first, so a login shell and all the jobs started from it. Session 0
+// holds kernel threads, and sig's own session is off limits unless `include_self` is set.
+fn session_groups(pids: &[i32], include_self: bool) -> Vec<i32> {
+    let own_session = System
+        .session(process::id() as i32)
+        .filter(|_| !include_self);
+    let mut sessions: Vec<i32> = Vec::new();
+    for sid in pids.iter().filter_map(|&pid| System.session(pid)) {
+        if sid > 0 && Some(sid) != own_session && !sessions.contains(&sid) {
+            sessions.push(sid);
+        }
+    }
+
+    let mut groups = Vec::new();
+    for sid in sessions {
+        let mut members: Vec<i32> = System
+            .pids()
+            .into_iter()
+            .filter(|&pid| System.session(pid) == Some(sid))
+            .filter_map(|pid| System.group(pid))
+            .collect();
+        // The leader's group has the session ID as its PGID
+        members.sort_by_key(|&pgid| pgid != sid);
+        for pgid in members {
+            if !groups.contains(&-pgid) {
+                groups.push(-pgid);
+            }
+        }
+    }
+    groups
+}
+
+// Signal one process and report what happened. Returns whether the signal was delivered,
+// or with signal 0 whether the process is alive.
+fn deliver(report: &mut Report, pid: i32, signal: i32, signal_name: &str) -> bool {
+    // Read before the process has a chance to go away
+    let cmdline = if report.is_json() {
+        command_line(pid.abs())
+    } else {
+        String::new()
+    };
+    let outcome = send_signal_to_pid(pid, signal);
+    if signal == 0 {
+        // Signal 0 delivers nothing, so say whether the process is there rather than "sent".
+        // A process we may not signal still exists.
+        let alive = matches!(outcome, Outcome::Sent | Outcome::PermissionDenied);
+        if report.is_json() {
+            let result = if alive { "alive" } else { outcome.as_str() };
+            report.record(pid, &cmdline, signal, result);
+        } else {
+            report_check(pid, outcome);
+        }
+        return alive;
+    }
+
+    if outcome != Outcome::Sent {
+        report.failed();
+    }
+    if report.is_json() {
+        report.record(pid, &cmdline, signal, outcome.as_str());
+        return outcome == Outcome::Sent;
+    }
+    match outcome {
+        Outcome::Sent => say!("Signal {} sent to {}", signal_name, describe(pid)),
+        Outcome::NoSuchProcess if pid < 0 => eprintln!("Error: No {} found", describe(pid)),
+        Outcome::NoSuchProcess => eprintln!("Error: No process with PID {} found", pid),
+        Outcome::PermissionDenied => eprintln!(
+            "Error: Permission denied to send signal to {}",
+            describe(pid)
+        ),
+        Outcome::InvalidSignal => eprintln!("Error: Invalid signal number"),
+        Outcome::Failed(errno) => eprintln!(
+            "Error: Failed to send signal to {}: {}",
+            describe(pid),
+            io::Error::from_raw_os_error(errno)
+        ),
+        // Only signal 0 looks for zombies
+        Outcome::Zombie => {}
+    }
+    outcome == Outcome::Sent
+}
+
+fn report_check(pid: i32, outcome: Outcome) {
+    match outcome {
+        Outcome::Sent => say!("Alive: {}", describe(pid)),
+        Outcome::PermissionDenied => {
+            say!("Alive: {} (owned by another user)", describe(pid))
+        }
+        Outcome::Zombie => say!("Exited: {} (zombie, not yet reaped)", describe(pid)),
+        Outcome::NoSuchProcess => say!("Not found: {}", describe(pid)),
+        Outcome::InvalidSignal | Outcome::Failed(_) => {
+            eprintln!(
+                "Error: Failed to check {} ({})",
+                describe(pid),
+                outcome.as_str()
+            )
+        }
+    }
+}
+
+// --tree: the given processes and everything below them. Children go first so that none of
+// them is re-parented to init, and out of reach, when its parent dies.
+fn signal_tree(report: &mut Report, roots: &[i32], delivery: &Delivery, include_self: bool) {
+    let excluded = if include_self {
+        Vec::new()
+    } else {
+        own_process_chain()
+    };
+    let pids: Vec<i32> = process_tree(roots)
+        .into_iter()
+        .filter(|pid| !excluded.contains(pid))
+        .collect();
+
+    if pids.is_empty() {
+        eprintln!("Error: No processes found to signal");
+        report.exit(1);
+    }
+
+    if delivery.dry_run {
+        report_dry_run(report, &pids, delivery);
+        return;
+    }
+
+    if delivery.verbose {
+        announce(report, &pids);
+    }
+    let signalled: Vec<i32> = pids
+        .iter()
+        .copied()
+        .filter(|&pid| deliver(report, pid, delivery.signal, delivery.signal_name))
+        .collect();
+    report.note(&format!(
+        "Signalled {} of {} process(es) in the tree",
+        signalled.len(),
+        pids.len()
+    ));
+
+    settle(report, &signalled, delivery);
+}
+
+// Every running process under `roots`, deepest first, each root after its descendants.
+// Roots that aren't running are left out.
+fn process_tree(roots: &[i32]) -> Vec<i32> {
+    let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+    let running = System.pids();
+    for &pid in &running {
+        if let Some(parent) = System.parent(pid) {
+            children.entry(parent).or_default().push(pid);
+        }
+    }
+
+    // Iterative post-order walk; a root that is below another root is only visited once
+    let mut order = Vec::new();
+    for &root in roots.iter().filter(|root| running.contains(root)) {
+        let mut stack = vec![(root, false)];
+        while let Some((pid, expanded)) = stack.pop() {
+            if expanded {
+                order.push(pid);
+            } else if !order.contains(&pid) {
+                stack.push((pid, true));
+                for &child in children.get(&pid).into_iter().flatten() {
+                    stack.push((child, false));
                 }
             }
         }
     }
+    order
+}
+
+// Send SIGTERM to every match, wait up to `grace` for them to exit, then SIGKILL the survivors
+fn graceful_kill(report: &mut Report, target: &str, grace: Duration, force: bool) {
+    let Matches { pids, unreadable } = if let Ok(pid) = target.parse::<i32>() {
+        Matches {
+            pids: vec![pid],
+            unreadable: 0,
+        }
+    } else {
+        let filter = Filter {
+            full: false,
+            include_self: false,
+            user: None,
+            older_than: None,
+            tty: None,
+            parent: None,
+        };
+        find_processes_by_name(&Pattern::Substring(target.to_string()), &filter)
+    };
+
+    if pids.is_empty() {
+        eprintln!("Error: No processes found with name '{}'", target);
+        hint_unreadable(unreadable);
+        report.exit(1);
+    }
+    if !force {
+        guard_critical(report, &pids, "SIGTERM");
+    }
+
+    let signalled: Vec<i32> = pids
+        .into_iter()
+        .filter(|&pid| deliver(report, pid, libc::SIGTERM, "SIGTERM"))
+        .collect();
+
+    if signalled.is_empty() {
+        return;
+    }
+
+    kill_survivors(report, &signalled, "SIGTERM", grace);
+}
+
+// What follows a successful send: --timeout escalation, then --wait for whatever was
+// signalled, SIGKILLed or not, to be gone
+fn settle(report: &mut Report, signalled: &[i32], delivery: &Delivery) {
+    if signalled.is_empty() {
+        return;
+    }
+    if let Some(timeout) = delivery.timeout {
+        kill_survivors(report, signalled, delivery.signal_name, timeout);
+    }
+    if let Some(wait) = delivery.wait {
+        wait_until_gone(report, signalled, wait);
+    }
+}
+
+// Wait up to `timeout` for the already signalled processes to exit, then SIGKILL the
+// survivors, reporting which way each one went
+fn kill_survivors(report: &mut Report, signalled: &[i32], signal_name: &str, timeout: Duration) {
+    let remaining = wait_for_exit(signalled, timeout);
+
+    for pid in signalled {
+        let (kind, id) = if *pid < 0 {
+            ("Process group", -pid)
+        } else {
+            ("Process", *pid)
+        };
+        if !remaining.contains(pid) {
+            report.note(&format!("{} {} exited after {}", kind, id, signal_name));
+        } else if deliver(report, *pid, libc::SIGKILL, "SIGKILL") {
+            report.note(&format!(
+                "{} {} did not exit within {}s, killed with SIGKILL",
+                kind,
+                id,
+                timeout.as_secs()
+            ));
+        }
+    }
+}
+
+// --wait: wait up to `wait` for the signalled processes to be gone, and fail naming any
+// that aren't
+fn wait_until_gone(report: &Report, signalled: &[i32], wait: Duration) {
+    let remaining = wait_for_exit(signalled, wait);
+    if remaining.is_empty() {
+        report.note(&format!("{} process(es) exited", signalled.len()));
+        return;
+    }
+    for pid in &remaining {
+        eprintln!(
+            "Error: {} still running after {}s",
+            describe(*pid),
+            wait.as_secs()
+        );
+    }
+    report.exit(1);
+}
+
+// Poll until all of `pids` have exited or `timeout` has passed, returning those still running
+fn wait_for_exit(pids: &[i32], timeout: Duration) -> Vec<i32> {
+    let deadline = Instant::now() + timeout;
+    let mut remaining: Vec<i32> = pids
+        .iter()
+        .copied()
+        .filter(|&pid| process_exists(pid))
+        .collect();
+    let mut interval = POLL_MIN;
+    while !remaining.is_empty() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep(interval.min(deadline - now));
+        interval = (interval * 2).min(POLL_MAX);
+        remaining.retain(|&pid| process_exists(pid));
+    }
+    remaining
+}
+
+// Signal 0 performs the permission and existence checks without delivering anything.
+// Zombies still pass that check, so treat them as gone once they stop running. A negative
+// PID asks whether anything is left in that process group.
+fn process_exists(pid: i32) -> bool {
+    let alive = unsafe { libc::kill(pid, 0) == 0 }
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    alive && !System.is_zombie(pid)
+}
+
+// Parse an age like "90", "30s", "5m", "2h" or "1d". A bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b's') => (&value[..value.len() - 1], 1),
+        Some(b'm') => (&value[..value.len() - 1], 60),
+        Some(b'h') => (&value[..value.len() - 1], 60 * 60),
+        Some(b'd') => (&value[..value.len() - 1], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!(
+            "'{}' is not a duration; use a number of seconds or an s, m, h or d suffix (e.g. 30s, 2h)",
+            value
+        ));
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too large", value))
+}
+
+// A user name or numeric UID to a UID; no value means whoever is running sig
+fn resolve_user(user: Option<&str>) -> Result<u32, String> {
+    let Some(user) = user else {
+        return Ok(unsafe { libc::getuid() });
+    };
+    if let Ok(uid) = user.parse::<u32>() {
+        return Ok(uid);
+    }
+    let name = CString::new(user).map_err(|_| format!("Unknown user '{}'", user))?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(format!("Unknown user '{}'", user));
+    }
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+// A terminal as ps shows it (pts/3, tty1) or as a path under /dev, to the device number
+// the process table reports for it
+fn resolve_tty(name: &str) -> Result<u64, String> {
+    let path = if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/dev/{}", name)
+    };
+    match fs::metadata(&path) {
+        Ok(meta) if meta.file_type().is_char_device() => Ok(meta.rdev()),
+        Ok(_) => Err(format!("'{}' is not a terminal", name)),
+        Err(_) => Err(format!("Unknown terminal '{}'", name)),
+    }
+}
+
+// sig itself and every process it runs under, up to init. `sig kill --all bash` from a
+// shell shouldn't take that shell (or the terminal around it) down with it.
+fn own_process_chain() -> Vec<i32> {
+    let mut chain = vec![process::id() as i32];
+    while let Some(parent) = chain.last().and_then(|&pid| System.parent(pid)) {
+        if parent <= 1 || chain.contains(&parent) {
+            break;
+        }
+        chain.push(parent);
+    }
+    chain
+}
+
+// What a scan by name found, and how many of the processes that didn't match could only be
+// partly read, so might have matched under sudo
+struct Matches {
+    pids: Vec<i32>,
+    unreadable: usize,
+}
+
+// A process matches when any of its names does, or with `full` its whole command line, and
+// it passes the rest of the filter. Unless `include_self` is set, sig and its ancestors
+// never match.
+fn find_processes_by_name(pattern: &Pattern, filter: &Filter) -> Matches {
+    let mut pids = Vec::new();
+    let mut unreadable = 0;
+    let excluded = if filter.include_self {
+        Vec::new()
+    } else {
+        own_process_chain()
+    };
+
+    for pid in System.pids() {
+        if excluded.contains(&pid) {
+            continue;
+        }
+        if filter.user.is_some_and(|uid| System.uid(pid) != Some(uid)) {
+            continue;
+        }
+        // A process whose age can't be read is gone, or too young to tell
+        if filter
+            .older_than
+            .is_some_and(|min| System.age(pid).is_none_or(|age| age <= min))
+        {
+            continue;
+        }
+        if filter.tty.is_some_and(|dev| System.tty(pid) != Some(dev)) {
+            continue;
+        }
+        if filter
+            .parent
+            .is_some_and(|ppid| System.parent(pid) != Some(ppid))
+        {
+            continue;
+        }
+        let candidates = if filter.full {
+            full_command_line(pid).into_iter().collect()
+        } else {
+            process_names(pid)
+        };
+        if candidates
+            .iter()
+            .any(|candidate| pattern.matches(candidate))
+        {
+            pids.push(pid);
+        } else if !System.is_readable(pid) {
+            unreadable += 1;
+        }
+    }
+
+    Matches { pids, unreadable }
+}
+
+// Why a name may have matched nothing: the processes it names may be there, but not ours
+// to look into. Root can read them all, so sudo is no help there.
+fn hint_unreadable(unreadable: usize) {
+    if unreadable > 0 && unsafe { libc::geteuid() } != 0 {
+        eprintln!(
+            "{} process(es) owned by other users could not be fully read; try again with sudo",
+            unreadable
+        );
+    }
+}
+
+// The arguments joined with spaces. None for kernel threads, which have no command line,
+// and processes that are gone.
+fn full_command_line(pid: i32) -> Option<String> {
+    let args: Vec<String> = System
+        .args(pid)
+        .into_iter()
+        .filter(|arg| !arg.is_empty())
+        .collect();
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.join(" "))
+    }
+}
+
+// Every name a process goes by: its comm (what the kernel calls it, also set for kernel
+// threads whose cmdline is empty), the name in its status, the binary it is running, and
+// its argv[0], which the process may have rewritten. Missing entries (exited, or not ours to
+// read) are left out.
+fn process_names(pid: i32) -> Vec<String> {
+    let mut names = Vec::new();
+
+    if let Some(comm) = System.comm(pid) {
+        names.push(comm);
+    }
+
+    if let Some(name) = System.status_name(pid) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    if let Some(exe) = System.exe(pid) {
+        if let Some(base) = exe.rsplit('/').next() {
+            names.push(base.to_string());
+        }
+    }
+
+    if let Some(cmd) = System.args(pid).into_iter().next() {
+        if !cmd.is_empty() {
+            // Both the executable name and the full command as given
+            let exe_name = cmd.split('/').next_back().unwrap_or(&cmd);
+            names.push(exe_name.to_string());
+            names.push(cmd);
+        }
+    }
 
-    pids
+    names
 }
@@ -0,0 +1,171 @@
+// How a process-name query is compared against the names a process goes by
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+
+pub enum Pattern {
+    // The default: the name contains the query anywhere
+    Substring(String),
+    // --exact: the name is the query
+    Exact(String),
+    // A query with `*` or `?` matches whole names, as in the shell
+    Glob(String),
+    // --regex: a POSIX extended regex found anywhere in the name
+    Regex(Regex),
+}
+
+impl Pattern {
+    // Pick the mode from the flags and the query itself. Fails on an invalid regex, so that
+    // nothing gets signalled because of a typo.
+    pub fn new(query: &str, exact: bool, regex: bool) -> Result<Pattern, String> {
+        if regex {
+            Regex::new(query).map(Pattern::Regex)
+        } else if exact {
+            Ok(Pattern::Exact(query.to_string()))
+        } else if query.contains(['*', '?']) {
+            Ok(Pattern::Glob(query.to_string()))
+        } else {
+            Ok(Pattern::Substring(query.to_string()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::Substring(query) => name.contains(query.as_str()),
+            Pattern::Exact(query) => name == query,
+            Pattern::Glob(glob) => glob_matches(glob, name),
+            Pattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+// `*` matches any run of characters and `?` any single one. On a mismatch, backtrack to the
+// last `*` and let it swallow one more character.
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut g, mut n) = (0, 0);
+    let mut star = None;
+
+    while n < name.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == name[n]) {
+            g += 1;
+            n += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            star = Some((g, n));
+            g += 1;
+        } else if let Some((star_g, star_n)) = star {
+            g = star_g + 1;
+            n = star_n + 1;
+            star = Some((star_g, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+// A compiled POSIX extended regex from the C library
+pub struct Regex {
+    compiled: libc::regex_t,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let c_pattern = CString::new(pattern)
+            .map_err(|_| format!("Invalid regex '{}': contains a NUL byte", pattern))?;
+        let mut compiled: libc::regex_t = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::regcomp(
+                &mut compiled,
+                c_pattern.as_ptr(),
+                libc::REG_EXTENDED | libc::REG_NOSUB,
+            )
+        };
+        if result != 0 {
+            let mut message = [0 as libc::c_char; 256];
+            let reason = unsafe {
+                libc::regerror(result, &compiled, message.as_mut_ptr(), message.len());
+                CStr::from_ptr(message.as_ptr())
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            return Err(format!("Invalid regex '{}': {}", pattern, reason));
+        }
+        Ok(Regex { compiled })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        // Names come from NUL-separated /proc files, so they never contain one themselves
+        let Ok(c_text) = CString::new(text) else {
+            return false;
+        };
+        unsafe { libc::regexec(&self.compiled, c_text.as_ptr(), 0, ptr::null_mut(), 0) == 0 }
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        unsafe { libc::regfree(&mut self.compiled) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, exact: bool, regex: bool, name: &str) -> bool {
+        Pattern::new(query, exact, regex).unwrap().matches(name)
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        for (glob, name) in [
+            ("*fox", "firefox"),
+            ("fire*", "firefox"),
+            ("f*x", "firefox"),
+            ("fire*fox*", "firefox"),
+            ("fire**", "firefox"),
+            ("?irefox", "firefox"),
+            ("fire?ox", "firefox"),
+            ("firefo?", "firefox"),
+            ("*", ""),
+        ] {
+            assert!(matches(glob, false, false, name), "{} {}", glob, name);
+        }
+    }
+
+    #[test]
+    fn globs_that_miss() {
+        for (glob, name) in [
+            ("*fox", "firefox-bin"),
+            ("fire*", "iceweasel"),
+            ("fire?ox", "fireox"),
+            ("?firefox", "firefox"),
+            ("firefox?", "firefox"),
+            ("f*z", "firefox"),
+        ] {
+            assert!(!matches(glob, false, false, name), "{} {}", glob, name);
+        }
+    }
+
+    #[test]
+    fn substring_and_exact() {
+        assert!(matches("fox", false, false, "firefox"));
+        assert!(!matches("wolf", false, false, "firefox"));
+        assert!(matches("firefox", true, false, "firefox"));
+        assert!(!matches("fox", true, false, "firefox"));
+        // With --exact a `*` is just a character
+        assert!(!matches("fire*", true, false, "firefox"));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches("^fire(fox|bird)$", false, true, "firefox"));
+        assert!(matches("fox", false, true, "firefox"));
+        assert!(!matches("^fox", false, true, "firefox"));
+        assert!(!matches("^fire(fox|bird)$", false, true, "firefox-bin"));
+        assert!(Pattern::new("(", false, true).is_err());
+    }
+}
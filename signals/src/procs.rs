@@ -0,0 +1,55 @@
+// What sig needs to know about running processes, from wherever the OS keeps it: /proc on
+// Linux, libproc on macOS and sysctl(KERN_PROC) on FreeBSD. The backend is picked at compile
+// time; signalling itself goes through kill(2), which is the same everywhere.
+
+use std::time::Duration;
+
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+
+#[cfg(target_os = "freebsd")]
+pub use freebsd::Sysctl as System;
+#[cfg(target_os = "linux")]
+pub use linux::Procfs as System;
+#[cfg(target_os = "macos")]
+pub use macos::Libproc as System;
+
+// Everything is looked up by PID on demand, so a process that exits in between simply has
+// nothing to report
+pub trait ProcessTable {
+    // Every process currently running
+    fn pids(&self) -> Vec<i32>;
+    // The short name the kernel keeps for the process, also set for kernel threads
+    fn comm(&self, pid: i32) -> Option<String>;
+    // The name in the process's status where the system keeps one apart from the comm, as
+    // shown by ps and top. None where there is no such name, or it can't be read.
+    fn status_name(&self, pid: i32) -> Option<String>;
+    // The path of the binary it is running
+    fn exe(&self, pid: i32) -> Option<String>;
+    // The arguments it was started with, argv[0] first, as the process may have rewritten
+    // them. Empty for kernel threads.
+    fn args(&self, pid: i32) -> Vec<String>;
+    // False when some of the above is withheld for lack of permission, as it is for other
+    // users' processes unless running as root
+    fn is_readable(&self, pid: i32) -> bool;
+    fn parent(&self, pid: i32) -> Option<i32>;
+    fn group(&self, pid: i32) -> Option<i32>;
+    // The session ID, which is the PID of the session leader (usually a login shell)
+    fn session(&self, pid: i32) -> Option<i32>;
+    // The real UID
+    fn uid(&self, pid: i32) -> Option<u32>;
+    fn is_zombie(&self, pid: i32) -> bool;
+    // How long ago the process started
+    fn age(&self, pid: i32) -> Option<Duration>;
+    // Resident memory in bytes
+    fn rss(&self, pid: i32) -> Option<u64>;
+    // The device number of the controlling terminal, as stat(2) gives it for the device
+    // node. None for processes without one, such as daemons.
+    fn tty(&self, pid: i32) -> Option<u64>;
+    // Where the process table is read from, or why it can't be, for --doctor
+    fn probe(&self) -> Result<String, String>;
+}
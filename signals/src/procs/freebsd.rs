@@ -0,0 +1,171 @@
+// sysctl(KERN_PROC) for the process list and per-process details
+
+use super::ProcessTable;
+use std::mem;
+use std::ptr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct Sysctl;
+
+impl ProcessTable for Sysctl {
+    fn pids(&self) -> Vec<i32> {
+        let mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PROC];
+        let Some(buffer) = sysctl(&mib) else {
+            return Vec::new();
+        };
+        let size = mem::size_of::<libc::kinfo_proc>();
+        buffer
+            .chunks_exact(size)
+            .map(|chunk| {
+                let proc: libc::kinfo_proc = unsafe { ptr::read_unaligned(chunk.as_ptr().cast()) };
+                proc.ki_pid
+            })
+            .collect()
+    }
+
+    fn comm(&self, pid: i32) -> Option<String> {
+        Some(c_chars(&kinfo(pid)?.ki_comm))
+    }
+
+    // The name of the main thread, which pthread_set_name_np can set apart from the comm;
+    // names longer than ki_tdname continue in ki_moretdname
+    fn status_name(&self, pid: i32) -> Option<String> {
+        let proc = kinfo(pid)?;
+        let name = c_chars(&proc.ki_tdname) + &c_chars(&proc.ki_moretdname);
+        (!name.is_empty()).then_some(name)
+    }
+
+    fn exe(&self, pid: i32) -> Option<String> {
+        let mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC,
+            libc::KERN_PROC_PATHNAME,
+            pid,
+        ];
+        let path = sysctl(&mib)?;
+        let path = path.split(|&b| b == 0).next()?;
+        Some(String::from_utf8_lossy(path).into_owned())
+    }
+
+    // The arguments, each NUL-terminated
+    fn args(&self, pid: i32) -> Vec<String> {
+        let mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_ARGS, pid];
+        let Some(args) = sysctl(&mib).filter(|args| !args.is_empty()) else {
+            return Vec::new();
+        };
+        let args = args.strip_suffix(&[0]).unwrap_or(&args);
+        args.split(|&b| b == 0)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect()
+    }
+
+    // KERN_PROC answers for every process it lists; those hidden by
+    // security.bsd.see_other_uids or a jail are left out of pids() altogether
+    fn is_readable(&self, _pid: i32) -> bool {
+        true
+    }
+
+    fn parent(&self, pid: i32) -> Option<i32> {
+        Some(kinfo(pid)?.ki_ppid)
+    }
+
+    fn group(&self, pid: i32) -> Option<i32> {
+        Some(kinfo(pid)?.ki_pgid)
+    }
+
+    fn session(&self, pid: i32) -> Option<i32> {
+        Some(kinfo(pid)?.ki_sid)
+    }
+
+    fn uid(&self, pid: i32) -> Option<u32> {
+        Some(kinfo(pid)?.ki_ruid)
+    }
+
+    fn is_zombie(&self, pid: i32) -> bool {
+        kinfo(pid).is_some_and(|proc| proc.ki_stat == libc::SZOMB)
+    }
+
+    fn age(&self, pid: i32) -> Option<Duration> {
+        let started = UNIX_EPOCH + Duration::from_secs(kinfo(pid)?.ki_start.tv_sec.max(0) as u64);
+        Some(
+            SystemTime::now()
+                .duration_since(started)
+                .unwrap_or_default(),
+        )
+    }
+
+    // ki_rssize is in pages
+    fn rss(&self, pid: i32) -> Option<u64> {
+        let pages = kinfo(pid)?.ki_rssize.max(0) as u64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+        Some(pages * page_size)
+    }
+
+    // NODEV (all ones) when there is no controlling terminal
+    fn tty(&self, pid: i32) -> Option<u64> {
+        let tdev = kinfo(pid)?.ki_tdev;
+        (tdev != u64::MAX).then_some(tdev)
+    }
+
+    fn probe(&self) -> Result<String, String> {
+        let mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PROC];
+        sysctl(&mib).ok_or_else(|| "sysctl kern.proc can't be read".to_string())?;
+        Ok("sysctl kern.proc".to_string())
+    }
+}
+
+fn kinfo(pid: i32) -> Option<libc::kinfo_proc> {
+    let mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PID, pid];
+    let buffer = sysctl(&mib)?;
+    if buffer.len() < mem::size_of::<libc::kinfo_proc>() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(buffer.as_ptr().cast()) })
+}
+
+// Read a sysctl of any size: ask for the size first, then leave some room for it to have
+// grown, as the process table does between the two calls
+fn sysctl(mib: &[libc::c_int]) -> Option<Vec<u8>> {
+    let mut len = 0;
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            ptr::null_mut(),
+            &mut len,
+            ptr::null(),
+            0,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u8; len + len / 8];
+    let mut len = buffer.len();
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            buffer.as_mut_ptr().cast(),
+            &mut len,
+            ptr::null(),
+            0,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+    buffer.truncate(len);
+    Some(buffer)
+}
+
+// A NUL-terminated name from a fixed-size field
+fn c_chars(chars: &[libc::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -0,0 +1,140 @@
+// /proc/[pid]: comm, exe, cmdline, stat and status
+
+use super::ProcessTable;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+pub struct Procfs;
+
+impl ProcessTable for Procfs {
+    fn pids(&self) -> Vec<i32> {
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect()
+    }
+
+    fn comm(&self, pid: i32) -> Option<String> {
+        let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(comm.trim_end_matches('\n').to_string())
+    }
+
+    // The "Name:" line of /proc/[pid]/status: the comm again, but with newlines and
+    // backslashes escaped the way ps shows them
+    fn status_name(&self, pid: i32) -> Option<String> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let line = status.lines().find(|line| line.starts_with("Name:"))?;
+        Some(
+            line["Name:".len()..]
+                .trim_start_matches([' ', '\t'])
+                .to_string(),
+        )
+    }
+
+    fn exe(&self, pid: i32) -> Option<String> {
+        // A replaced binary shows up as "/usr/bin/foo (deleted)"
+        let exe = fs::read_link(format!("/proc/{}/exe", pid)).ok()?;
+        let exe = exe.to_string_lossy();
+        Some(exe.strip_suffix(" (deleted)").unwrap_or(&exe).to_string())
+    }
+
+    fn args(&self, pid: i32) -> Vec<String> {
+        // cmdline uses null bytes as separators, and ends with one
+        fs::read_to_string(format!("/proc/{}/cmdline", pid))
+            .map(|cmdline| cmdline.split_terminator('\0').map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    // The exe link is the first thing to go: reading it needs the same access as ptrace.
+    // Kernel threads have none to read, which is not a permission problem.
+    fn is_readable(&self, pid: i32) -> bool {
+        match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Err(e) => e.kind() != io::ErrorKind::PermissionDenied,
+            Ok(_) => true,
+        }
+    }
+
+    fn parent(&self, pid: i32) -> Option<i32> {
+        stat_fields(pid)?.get(1)?.parse().ok()
+    }
+
+    fn group(&self, pid: i32) -> Option<i32> {
+        stat_fields(pid)?.get(2)?.parse().ok()
+    }
+
+    fn session(&self, pid: i32) -> Option<i32> {
+        stat_fields(pid)?.get(3)?.parse().ok()
+    }
+
+    // The first of the "Uid:" line of /proc/[pid]/status (real, effective, saved, fs)
+    fn uid(&self, pid: i32) -> Option<u32> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let line = status.lines().find(|line| line.starts_with("Uid:"))?;
+        line["Uid:".len()..].split_whitespace().next()?.parse().ok()
+    }
+
+    fn is_zombie(&self, pid: i32) -> bool {
+        stat_fields(pid)
+            .map(|fields| fields.first().is_some_and(|state| state == "Z"))
+            .unwrap_or(false)
+    }
+
+    // The system uptime less the start time, which field 22 of /proc/[pid]/stat gives in
+    // clock ticks since boot
+    fn age(&self, pid: i32) -> Option<Duration> {
+        let start_ticks: u64 = stat_fields(pid)?.get(19)?.parse().ok()?;
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks_per_sec <= 0 {
+            return None;
+        }
+        let uptime = fs::read_to_string("/proc/uptime").ok()?;
+        let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+        let started = start_ticks as f64 / ticks_per_sec as f64;
+        Some(Duration::from_secs_f64((uptime - started).max(0.0)))
+    }
+
+    // The second field of /proc/[pid]/statm, in pages
+    fn rss(&self, pid: i32) -> Option<u64> {
+        let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        Some(pages * page_size())
+    }
+
+    // Field 7 of /proc/[pid]/stat packs the major number into bits 8-15 and the minor into
+    // bits 0-7 and 20-31; 0 means no terminal
+    fn tty(&self, pid: i32) -> Option<u64> {
+        let tty_nr: u32 = stat_fields(pid)?.get(4)?.parse().ok()?;
+        if tty_nr == 0 {
+            return None;
+        }
+        let major = (tty_nr >> 8) & 0xfff;
+        let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+        Some(libc::makedev(major, minor))
+    }
+
+    // In a container without /proc mounted every lookup above finds nothing, which looks
+    // the same as no process matching
+    fn probe(&self) -> Result<String, String> {
+        fs::read_dir("/proc").map_err(|e| format!("/proc can't be read: {}", e))?;
+        if fs::metadata("/proc/self/stat").is_err() {
+            return Err("/proc is not mounted (there is no /proc/self/stat)".to_string());
+        }
+        Ok("/proc".to_string())
+    }
+}
+
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64
+}
+
+// The fields of /proc/[pid]/stat after the comm: state, ppid, pgrp, ...
+fn stat_fields(pid: i32) -> Option<Vec<String>> {
+    // The fields follow the parenthesised comm, which may itself contain spaces
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let after_comm = stat.rsplit_once(')')?.1;
+    Some(after_comm.split_whitespace().map(String::from).collect())
+}
@@ -0,0 +1,203 @@
+// libproc for the process list and per-process details, sysctl(KERN_PROCARGS2) for the
+// arguments
+
+use super::ProcessTable;
+use std::mem;
+use std::ptr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct Libproc;
+
+impl ProcessTable for Libproc {
+    fn pids(&self) -> Vec<i32> {
+        // Asking with no buffer gives the current count; leave room for processes started
+        // in between
+        let count = unsafe { libc::proc_listallpids(ptr::null_mut(), 0) };
+        if count <= 0 {
+            return Vec::new();
+        }
+        let mut pids = vec![0 as libc::pid_t; count as usize + 64];
+        let size = (pids.len() * mem::size_of::<libc::pid_t>()) as libc::c_int;
+        let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr().cast(), size) };
+        pids.truncate(count.max(0) as usize);
+        pids
+    }
+
+    fn comm(&self, pid: i32) -> Option<String> {
+        // pbi_name is the longer of the two, but only set for some processes
+        let info = bsd_info(pid)?;
+        let name = c_chars(&info.pbi_name);
+        Some(if name.is_empty() {
+            c_chars(&info.pbi_comm)
+        } else {
+            name
+        })
+    }
+
+    // comm already prefers pbi_name, the only other name libproc has
+    fn status_name(&self, _pid: i32) -> Option<String> {
+        None
+    }
+
+    fn exe(&self, pid: i32) -> Option<String> {
+        let mut path = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+        let len = unsafe { libc::proc_pidpath(pid, path.as_mut_ptr().cast(), path.len() as u32) };
+        if len <= 0 {
+            return None;
+        }
+        path.truncate(len as usize);
+        Some(String::from_utf8_lossy(&path).into_owned())
+    }
+
+    // KERN_PROCARGS2 gives argc, the executable path, NUL padding, then argc arguments, each
+    // NUL-terminated, followed by the environment
+    fn args(&self, pid: i32) -> Vec<String> {
+        let Some(buffer) = procargs(pid) else {
+            return Vec::new();
+        };
+        let Some((argc, rest)) = buffer.split_first_chunk::<4>() else {
+            return Vec::new();
+        };
+        let argc = i32::from_ne_bytes(*argc).max(0) as usize;
+        let Some(path_end) = rest.iter().position(|&b| b == 0) else {
+            return Vec::new();
+        };
+        let args_start = rest[path_end..]
+            .iter()
+            .position(|&b| b != 0)
+            .map_or(rest.len(), |skip| path_end + skip);
+        rest[args_start..]
+            .split(|&b| b == 0)
+            .take(argc)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect()
+    }
+
+    // KERN_PROCARGS2 is refused for other users' processes
+    fn is_readable(&self, pid: i32) -> bool {
+        procargs(pid).is_some()
+    }
+
+    fn parent(&self, pid: i32) -> Option<i32> {
+        Some(bsd_info(pid)?.pbi_ppid as i32)
+    }
+
+    fn group(&self, pid: i32) -> Option<i32> {
+        Some(bsd_info(pid)?.pbi_pgid as i32)
+    }
+
+    // proc_bsdinfo has no session ID, but getsid(2) answers for any process
+    fn session(&self, pid: i32) -> Option<i32> {
+        let sid = unsafe { libc::getsid(pid) };
+        (sid >= 0).then_some(sid)
+    }
+
+    fn uid(&self, pid: i32) -> Option<u32> {
+        Some(bsd_info(pid)?.pbi_ruid)
+    }
+
+    fn is_zombie(&self, pid: i32) -> bool {
+        bsd_info(pid).is_some_and(|info| info.pbi_status == libc::SZOMB)
+    }
+
+    fn age(&self, pid: i32) -> Option<Duration> {
+        let started = UNIX_EPOCH + Duration::from_secs(bsd_info(pid)?.pbi_start_tvsec);
+        Some(
+            SystemTime::now()
+                .duration_since(started)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn rss(&self, pid: i32) -> Option<u64> {
+        let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+        let len = unsafe {
+            libc::proc_pidinfo(
+                pid,
+                libc::PROC_PIDTASKINFO,
+                0,
+                (&mut info as *mut libc::proc_taskinfo).cast(),
+                size,
+            )
+        };
+        (len == size).then_some(info.pti_resident_size)
+    }
+
+    // NODEV (-1) when there is no controlling terminal
+    fn tty(&self, pid: i32) -> Option<u64> {
+        let tdev = bsd_info(pid)?.e_tdev;
+        (tdev != u32::MAX).then_some(tdev as u64)
+    }
+
+    fn probe(&self) -> Result<String, String> {
+        if unsafe { libc::proc_listallpids(ptr::null_mut(), 0) } <= 0 {
+            return Err("libproc lists no processes".to_string());
+        }
+        Ok("libproc".to_string())
+    }
+}
+
+fn bsd_info(pid: i32) -> Option<libc::proc_bsdinfo> {
+    let mut info: libc::proc_bsdinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let len = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            (&mut info as *mut libc::proc_bsdinfo).cast(),
+            size,
+        )
+    };
+    (len == size).then_some(info)
+}
+
+fn procargs(pid: i32) -> Option<Vec<u8>> {
+    // The arguments and environment together are at most ARG_MAX bytes
+    let mut arg_max: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>();
+    let mut mib = [libc::CTL_KERN, libc::KERN_ARGMAX];
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            2,
+            (&mut arg_max as *mut libc::c_int).cast(),
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 || arg_max <= 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u8; arg_max as usize];
+    let mut len = buffer.len();
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid];
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            3,
+            buffer.as_mut_ptr().cast(),
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+    buffer.truncate(len);
+    Some(buffer)
+}
+
+// A NUL-terminated name from a fixed-size field
+fn c_chars(chars: &[libc::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -0,0 +1,120 @@
+// What came of signalling each process, printed as it happens or, with --json, collected
+// and printed as one array at the end
+
+use output::say;
+use std::process;
+
+// Exit statuses above this mean something else to shells: 126 and 127 for commands that
+// can't run, 128+N for a death by signal N
+const MAX_FAILURE_STATUS: usize = 125;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Sent,
+    // Only reported for signal 0: the process has exited but isn't reaped yet
+    Zombie,
+    NoSuchProcess,
+    PermissionDenied,
+    InvalidSignal,
+    Failed(i32),
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Sent => "sent",
+            Outcome::Zombie => "zombie",
+            Outcome::NoSuchProcess => "no_such_process",
+            Outcome::PermissionDenied => "permission_denied",
+            Outcome::InvalidSignal => "invalid_signal",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+}
+
+pub struct Report {
+    json: bool,
+    entries: Vec<String>,
+    // Signals that couldn't be sent
+    failures: usize,
+}
+
+impl Report {
+    pub fn new(json: bool) -> Report {
+        Report {
+            json,
+            entries: Vec::new(),
+            failures: 0,
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    // A line of human-readable output, which --json and --quiet leave out
+    pub fn note(&self, line: &str) {
+        if !self.json {
+            say!("{}", line);
+        }
+    }
+
+    // One element of the --json array, e.g.
+    // {"pid":1234,"cmdline":"nginx: worker process","signal":15,"result":"sent"}
+    // A process group is reported with "pgid" in place of "pid".
+    pub fn record(&mut self, pid: i32, cmdline: &str, signal: i32, result: &str) {
+        let (key, id) = if pid < 0 {
+            ("pgid", -pid)
+        } else {
+            ("pid", pid)
+        };
+        self.entries.push(format!(
+            "{{\"{}\":{},\"cmdline\":{},\"signal\":{},\"result\":{}}}",
+            key,
+            id,
+            json_string(cmdline),
+            signal,
+            json_string(result)
+        ));
+    }
+
+    pub fn failed(&mut self) {
+        self.failures += 1;
+    }
+
+    // Print what has been collected, then exit with the number of signals that couldn't be
+    // sent if there were any, so a script can tell how many of an --all batch failed
+    pub fn finish(&self) {
+        self.print_json();
+        if self.failures > 0 {
+            process::exit(self.failures.min(MAX_FAILURE_STATUS) as i32);
+        }
+    }
+
+    // Exit, printing what has been collected so far first
+    pub fn exit(&self, code: i32) -> ! {
+        self.print_json();
+        process::exit(code)
+    }
+
+    fn print_json(&self) {
+        if self.json {
+            println!("[{}]", self.entries.join(","));
+        }
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,348 @@
+// The signals sig knows by name, in one table that the parser, --list, --list --json, the
+// usage text and the shell completions all read, so adding a signal is one more entry
+
+use crate::report::json_string;
+
+// What a process does with a signal it has no handler for
+#[derive(Clone, Copy)]
+pub enum Action {
+    Terminate,
+    // Terminate and dump core
+    Core,
+    Stop,
+    Continue,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Terminate => "terminate",
+            Action::Core => "core",
+            Action::Stop => "stop",
+            Action::Continue => "continue",
+        }
+    }
+}
+
+// A signal accepted by name. The first name is the full one; any of them may be given with
+// or without the `sig` prefix, in any case.
+pub struct SignalDef {
+    pub names: &'static [&'static str],
+    pub number: i32,
+    pub action: Action,
+    // SIGKILL and SIGSTOP can't be caught, blocked or ignored
+    pub catchable: bool,
+    pub description: &'static str,
+}
+
+impl SignalDef {
+    pub fn name(&self) -> &'static str {
+        self.names[0]
+    }
+
+    pub fn aliases(&self) -> &'static [&'static str] {
+        &self.names[1..]
+    }
+}
+
+pub const SIGNALS: &[SignalDef] = &[
+    SignalDef {
+        names: &["SIGHUP", "hup", "hangup"],
+        number: libc::SIGHUP,
+        action: Action::Terminate,
+        catchable: true,
+        description: "Hangup; many daemons reload their config",
+    },
+    SignalDef {
+        names: &["SIGINT", "int", "interrupt"],
+        number: libc::SIGINT,
+        action: Action::Terminate,
+        catchable: true,
+        description: "Interrupt, as sent by Ctrl+C",
+    },
+    SignalDef {
+        names: &["SIGQUIT", "quit"],
+        number: libc::SIGQUIT,
+        action: Action::Core,
+        catchable: true,
+        description: "Quit and dump core, as sent by Ctrl+\\",
+    },
+    SignalDef {
+        names: &["SIGKILL", "kill"],
+        number: libc::SIGKILL,
+        action: Action::Terminate,
+        catchable: false,
+        description: "Kill immediately; cannot be caught or ignored",
+    },
+    SignalDef {
+        names: &["SIGUSR1", "usr1"],
+        number: libc::SIGUSR1,
+        action: Action::Terminate,
+        catchable: true,
+        description: "User-defined signal 1",
+    },
+    SignalDef {
+        names: &["SIGUSR2", "usr2"],
+        number: libc::SIGUSR2,
+        action: Action::Terminate,
+        catchable: true,
+        description: "User-defined signal 2",
+    },
+    SignalDef {
+        names: &["SIGTERM", "term", "terminate"],
+        number: libc::SIGTERM,
+        action: Action::Terminate,
+        catchable: true,
+        description: "Ask the process to exit cleanly",
+    },
+    SignalDef {
+        names: &["SIGCONT", "cont"],
+        number: libc::SIGCONT,
+        action: Action::Continue,
+        catchable: true,
+        description: "Resume a stopped process",
+    },
+    SignalDef {
+        names: &["SIGSTOP", "stop"],
+        number: libc::SIGSTOP,
+        action: Action::Stop,
+        catchable: false,
+        description: "Pause the process; cannot be caught or ignored",
+    },
+];
+
+// A signal as given on the command line: a number, a real-time signal or a name from the
+// table. Ok(None) if it is none of these; an error if it is a real-time signal out of range.
+pub fn parse(name: &str) -> Result<Option<i32>, String> {
+    if let Ok(num) = name.parse::<i32>() {
+        return Ok(Some(num));
+    }
+    match parse_rt(name) {
+        Some(rt) => rt.map(Some),
+        None => Ok(by_name(name)),
+    }
+}
+
+// The usual short name of each signal: hup, int, quit, ...
+pub fn short_names() -> Vec<&'static str> {
+    SIGNALS.iter().map(|def| def.aliases()[0]).collect()
+}
+
+// Case doesn't matter and the `sig` prefix is optional, so SIGTERM, sigterm, TERM and term
+// are all the same signal, as are SIGHANGUP and hangup
+fn by_name(name: &str) -> Option<i32> {
+    let lower = name.to_lowercase();
+    let bare = lower.strip_prefix("sig").unwrap_or(&lower);
+    SIGNALS
+        .iter()
+        .find(|def| def.name()[3..].eq_ignore_ascii_case(bare) || def.aliases().contains(&bare))
+        .map(|def| def.number)
+}
+
+// Real-time signals as `rtmin`, `rtmin+N`, `rtmax` or `rtmax-N`, optionally `sig`-prefixed.
+// None if the name isn't one of these; an error if the offset leaves the RT range.
+fn parse_rt(name: &str) -> Option<Result<i32, String>> {
+    let lower = name.to_lowercase();
+    let lower = lower.strip_prefix("sig").unwrap_or(&lower);
+    let (min, max) = rt_range()?;
+
+    let (base, sign, rest) = if let Some(rest) = lower.strip_prefix("rtmin") {
+        (min, 1, rest.strip_prefix('+'))
+    } else if let Some(rest) = lower.strip_prefix("rtmax") {
+        (max, -1, rest.strip_prefix('-'))
+    } else {
+        return None;
+    };
+
+    // A bare `rtmin`/`rtmax` leaves nothing after the name; anything else needs its sign
+    let offset = match rest {
+        Some(digits) => digits.parse::<u8>().ok().map(i32::from),
+        None if lower.len() == "rtmin".len() => Some(0),
+        None => None,
+    };
+    let Some(offset) = offset else {
+        return Some(Err(format!("Invalid real-time signal '{}'", name)));
+    };
+
+    if offset > max - min {
+        return Some(Err(format!(
+            "'{}' is out of range: real-time signals run from SIGRTMIN ({}) to SIGRTMAX ({}), \
+             so the offset must be at most {}",
+            name,
+            min,
+            max,
+            max - min
+        )));
+    }
+    Some(Ok(base + sign * offset))
+}
+
+// SIGRTMIN and SIGRTMAX, on the systems that have real-time signals
+#[cfg(target_os = "linux")]
+pub fn rt_range() -> Option<(i32, i32)> {
+    Some((libc::SIGRTMIN(), libc::SIGRTMAX()))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rt_range() -> Option<(i32, i32)> {
+    None
+}
+
+pub fn print_list() {
+    println!(
+        "{:<8} {:>3}  {:<16} {:<10} DESCRIPTION",
+        "NAME", "NUM", "ALSO", "DEFAULT"
+    );
+    for def in SIGNALS {
+        println!(
+            "{:<8} {:>3}  {:<16} {:<10} {}",
+            def.name(),
+            def.number,
+            def.aliases().join(", "),
+            def.action.as_str(),
+            def.description
+        );
+    }
+    if let Some((min, max)) = rt_range() {
+        println!(
+            "\nReal-time signals {}-{} as rtmin, rtmin+N, rtmax or rtmax-N (N up to {})",
+            min,
+            max,
+            max - min
+        );
+    }
+    println!("Any other signal can be sent by number, e.g. 'sig 28 <pid>'");
+}
+
+// --list --json: the same table for GUIs and shell scripts, one object per signal, e.g.
+// {"name":"SIGTERM","aliases":["term","terminate"],"number":15,"default_action":"terminate",
+//  "catchable":true,"description":"Ask the process to exit cleanly"}
+// followed by each real-time signal where there are any
+pub fn print_list_json() {
+    let mut objects: Vec<String> = SIGNALS
+        .iter()
+        .map(|def| {
+            signal_json(
+                def.name(),
+                def.aliases(),
+                def.number,
+                def.action,
+                def.catchable,
+                def.description,
+            )
+        })
+        .collect();
+    // Named after the nearer end of the range, as kill -l does
+    if let Some((min, max)) = rt_range() {
+        for number in min..=max {
+            let alias = if number - min <= (max - min) / 2 {
+                match number - min {
+                    0 => "rtmin".to_string(),
+                    offset => format!("rtmin+{}", offset),
+                }
+            } else {
+                match max - number {
+                    0 => "rtmax".to_string(),
+                    offset => format!("rtmax-{}", offset),
+                }
+            };
+            objects.push(signal_json(
+                &format!("SIG{}", alias.to_uppercase()),
+                &[alias.as_str()],
+                number,
+                Action::Terminate,
+                true,
+                "Real-time signal",
+            ));
+        }
+    }
+    println!("[{}]", objects.join(","));
+}
+
+fn signal_json(
+    name: &str,
+    aliases: &[&str],
+    number: i32,
+    action: Action,
+    catchable: bool,
+    description: &str,
+) -> String {
+    let aliases: Vec<String> = aliases.iter().map(|alias| json_string(alias)).collect();
+    format!(
+        "{{\"name\":{},\"aliases\":[{}],\"number\":{},\"default_action\":{},\
+         \"catchable\":{},\"description\":{}}}",
+        json_string(name),
+        aliases.join(","),
+        number,
+        json_string(action.as_str()),
+        catchable,
+        json_string(description)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_with_and_without_prefix_in_any_case() {
+        for name in ["SIGTERM", "sigterm", "SigTerm", "TERM", "term", "terminate"] {
+            assert_eq!(parse(name), Ok(Some(libc::SIGTERM)), "{}", name);
+        }
+        for name in [
+            "SIGHUP",
+            "SIGHANGUP",
+            "sighangup",
+            "HANGUP",
+            "hangup",
+            "hup",
+        ] {
+            assert_eq!(parse(name), Ok(Some(libc::SIGHUP)), "{}", name);
+        }
+        assert_eq!(parse("kill"), Ok(Some(libc::SIGKILL)));
+        assert_eq!(parse("9"), Ok(Some(9)));
+    }
+
+    #[test]
+    fn unknown_names() {
+        for name in ["bogus", "sig", "", "SIGSIGTERM", "ter", "-9x"] {
+            assert_eq!(parse(name), Ok(None), "{}", name);
+        }
+    }
+
+    #[test]
+    fn every_table_name_parses() {
+        for def in SIGNALS {
+            for name in def.names {
+                assert_eq!(parse(name), Ok(Some(def.number)), "{}", name);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn real_time_signals() {
+        let (min, max) = rt_range().unwrap();
+        assert_eq!(parse("rtmin"), Ok(Some(min)));
+        assert_eq!(parse("SIGRTMIN"), Ok(Some(min)));
+        assert_eq!(parse("rtmin+3"), Ok(Some(min + 3)));
+        assert_eq!(parse("sigrtmax-2"), Ok(Some(max - 2)));
+        assert_eq!(parse("RTMAX"), Ok(Some(max)));
+        let span = max - min;
+        assert_eq!(parse(&format!("rtmin+{}", span)), Ok(Some(max)));
+        assert_eq!(parse(&format!("rtmax-{}", span)), Ok(Some(min)));
+
+        assert!(parse(&format!("rtmin+{}", span + 1)).is_err());
+        assert!(parse(&format!("rtmax-{}", span + 1)).is_err());
+        assert!(parse("rtmin-1").is_err());
+        assert!(parse("rtmax+1").is_err());
+        assert!(parse("rtmin+").is_err());
+        assert!(parse("rtminx").is_err());
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[test]
+    fn no_real_time_signals() {
+        assert_eq!(parse("rtmin+1"), Ok(None));
+    }
+}